@@ -0,0 +1,146 @@
+//!
+//! Access control: `#+ACCESS: group…` restricts the heading it is under, along with everything
+//! below it, to viewers holding at least one of the listed groups.
+//!
+use std::collections::HashSet;
+
+use crate::parse::{
+    refill_tocs, renumber_footnotes, AstNode, BackrefAstNode, Directive, PassedSyntaxTree,
+};
+
+/// Whether a viewer holding `groups` may see this heading's subtree.
+/// Every `#+ACCESS:` directly under the heading has to be satisfied.
+pub fn may_view(children: &[BackrefAstNode], groups: &HashSet<String>) -> bool {
+    children.iter().all(|child| match &child.inner {
+        AstNode::Directive(Directive::Access(allowed)) => {
+            allowed.iter().any(|group| groups.contains(group))
+        }
+        _ => true,
+    })
+}
+
+/// Copy of the tree without the subtrees `groups` may not see.
+/// Anything rendered for a viewer (pages, nav, dumps, feeds) should come from this.
+pub fn strip_restricted(nodes: &[BackrefAstNode], groups: &HashSet<String>) -> PassedSyntaxTree {
+    let mut stripped = strip(nodes, groups);
+    // Neither may the headings and footnotes of stripped subtrees, through a TOC or footnote list.
+    refill_tocs(&mut stripped);
+    renumber_footnotes(&mut stripped);
+    stripped
+}
+
+fn strip(nodes: &[BackrefAstNode], groups: &HashSet<String>) -> PassedSyntaxTree {
+    nodes
+        .iter()
+        .filter_map(|node| match &node.inner {
+            AstNode::Heading { children, .. } if !may_view(children, groups) => None,
+            _ => {
+                let mut node = node.clone();
+                if let Some(children) = node.inner.children_mut() {
+                    *children = strip(children, groups);
+                }
+                Some(node)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_n_pass, Footnote, TocEntry};
+
+    /// The footnote lists left in `nodes`, as (number, label, references)
+    fn footnotes(nodes: &[BackrefAstNode]) -> Vec<(usize, Option<String>, usize)> {
+        let mut found = vec![];
+        for node in nodes {
+            if let AstNode::Footnotes(footnotes) = &node.inner {
+                found.extend(footnotes.iter().map(
+                    |Footnote {
+                         number,
+                         label,
+                         references,
+                         ..
+                     }| (*number, label.clone(), *references),
+                ));
+            }
+            if let Some(children) = node.inner.children() {
+                found.extend(footnotes(children));
+            }
+        }
+        found
+    }
+
+    const PAGE: &str = "\
+* page :page:
+** secret
+#+ACCESS: friends
+only friends[fn:secret] see this[fn:shared]
+
+[fn:secret] the secret
+** open
+everyone sees this[fn:shared]
+
+[fn:shared] shared note
+";
+
+    #[test]
+    fn restricted_footnotes_are_left_out() {
+        let ast = parse_n_pass(PAGE).unwrap();
+        assert_eq!(
+            footnotes(&strip_restricted(&ast, &HashSet::new())),
+            vec![(1, Some("shared".into()), 1)]
+        );
+
+        let friends = HashSet::from(["friends".to_string()]);
+        assert_eq!(
+            footnotes(&strip_restricted(&ast, &friends)),
+            vec![(1, Some("secret".into()), 1), (2, Some("shared".into()), 2)]
+        );
+    }
+
+    /// The titles in the first `#+TOC:` of `nodes`, nested ones after their parents
+    fn toc_titles(nodes: &[BackrefAstNode]) -> Option<Vec<String>> {
+        fn titles(entries: &[TocEntry], out: &mut Vec<String>) {
+            for entry in entries {
+                out.push(entry.title.clone());
+                titles(&entry.children, out);
+            }
+        }
+
+        nodes.iter().find_map(|node| match &node.inner {
+            AstNode::Directive(Directive::Toc { entries, .. }) => {
+                let mut out = vec![];
+                titles(entries, &mut out);
+                Some(out)
+            }
+            _ => toc_titles(node.inner.children()?),
+        })
+    }
+
+    #[test]
+    fn restricted_headings_are_left_out_of_tocs() {
+        let ast = parse_n_pass(
+            "\
+* page :page:
+#+TOC: headlines
+** open
+*** open below
+** secret
+#+ACCESS: friends
+*** secret below
+",
+        )
+        .unwrap();
+        assert_eq!(
+            toc_titles(&strip_restricted(&ast, &HashSet::new())).unwrap(),
+            vec!["open", "open below"]
+        );
+
+        let friends = HashSet::from(["friends".to_string()]);
+        assert_eq!(
+            toc_titles(&strip_restricted(&ast, &friends)).unwrap(),
+            vec!["open", "open below", "secret", "secret below"]
+        );
+    }
+}
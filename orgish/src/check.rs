@@ -0,0 +1,363 @@
+//!
+//! Checks over a [`PassedSyntaxTree`] that catch mistakes before they are deployed.
+//!
+use std::{collections::HashSet, iter, mem};
+
+use anyhow::{bail, Result};
+
+use crate::treewalk::escape_html;
+
+pub use crate::parse::Diagnostic;
+use crate::parse::{
+    has_scheme, route_index, AstNode, BackrefAstNode, BlockExprNode, BlockExprTree, Directive,
+    LinkTarget, PassedSyntaxTree, Route, RouteIndex, Span,
+};
+
+// Lives in `parse` so the parser can report its own errors; rendering it is the checker's job.
+impl Diagnostic {
+    /// The message and the lines around it in `source`, the contents of `file`, with a marker
+    /// under where it points. The HTML counterpart of the terminal output of
+    /// [`crate::parse::parse_n_pass`].
+    pub fn to_html(&self, file: &str, source: &str) -> String {
+        let pos = self.span.start;
+        let line_range: usize = 3;
+        let first_line = pos.line.saturating_sub(line_range / 2).max(1);
+
+        let mut buf = format!(
+            r#"<div class="diagnostic"><p>{}</p><pre>"#,
+            escape_html(&format!("{file}:{self}"))
+        );
+        for (idx, line) in source
+            .lines()
+            .skip(first_line - 1)
+            .take(line_range)
+            .enumerate()
+        {
+            let line_num = format!("{}|    ", idx + first_line);
+            buf.push_str(&format!(
+                r#"<span class="line-number">{}</span>{}"#,
+                line_num,
+                escape_html(line)
+            ));
+            buf.push('\n');
+            if idx + first_line == pos.line {
+                let spaces = " ".repeat(line_num.len() + pos.column - 1);
+                buf.push_str(&format!("{spaces}<mark>^</mark>\n"));
+            }
+        }
+        buf.push_str("</pre></div>");
+        buf
+    }
+}
+
+/// Paths the site server answers itself instead of with a page, so links to them aren't broken.
+/// Keep in sync with `fallback_handler` in the site crate.
+pub const SERVED_PATHS: [&str; 4] = ["/", "/feed.xml", "/sitemap.xml", "/robots.txt"];
+/// Like [`SERVED_PATHS`], for everything under these
+pub const SERVED_PREFIXES: [&str; 2] = ["/static/", "/image-cache/"];
+
+fn is_served(url: &str) -> bool {
+    let path = match url.find(&['?', '#'][..]) {
+        Some(end) => &url[..end],
+        None => url,
+    };
+    SERVED_PATHS.contains(&path) || SERVED_PREFIXES.iter().any(|p| path.starts_with(p))
+}
+
+struct LinkChecker {
+    index: RouteIndex,
+    pages: HashSet<String>,
+    /// (page, section) pairs we have seen
+    sections: HashSet<(Option<String>, String)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl LinkChecker {
+    fn report(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            span,
+            message,
+            expected: vec![],
+        });
+    }
+
+    fn check_bet(&mut self, bet: &BlockExprTree, page: Option<&str>) {
+        for ben in bet {
+            if let BlockExprNode::Link(target, _) = &ben.inner {
+                self.check_target(target, ben.span, page);
+            }
+            if let Some(children) = ben.inner.children() {
+                self.check_bet(children, page);
+            }
+        }
+    }
+
+    fn check_target(&mut self, target: &LinkTarget, span: Span, page: Option<&str>) {
+        match target {
+            // pass3 rewrites everything it can resolve, so these are all broken.
+            LinkTarget::Heading { title } => {
+                self.report(span, format!("no heading titled {:?}", title))
+            }
+            LinkTarget::External(url) if !has_scheme(url) && !is_served(url) => {
+                let (path, fragment) = url.split_once('#').unwrap_or((url, ""));
+                let pg = match path.trim_start_matches('/') {
+                    "" => page.unwrap_or_default(),
+                    pg => pg,
+                };
+                let message = match self.index.pages.get(pg) {
+                    Some(_) => format!("no section #{} on page {:?}", fragment, pg),
+                    None => format!("no page {:?} for link {:?}", pg, url),
+                };
+                self.report(span, message)
+            }
+            LinkTarget::External(_) | LinkTarget::Internal { .. } => {}
+        }
+    }
+
+    fn check_nodes(&mut self, nodes: &[BackrefAstNode], page: Option<&str>) {
+        for node in nodes {
+            let mut page = page.map(str::to_string);
+            if let AstNode::Heading { routing, .. } = &node.inner {
+                match routing {
+                    Some(Route::Page(pg)) => {
+                        if !self.pages.insert(pg.clone()) {
+                            self.report(
+                                node.span,
+                                format!("page {:?} is defined more than once", pg),
+                            );
+                        }
+                        page = Some(pg.clone());
+                    }
+                    Some(Route::Section(section)) => {
+                        if !self.sections.insert((page.clone(), section.clone())) {
+                            self.report(
+                                node.span,
+                                format!("section #{} is defined more than once", section),
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if let AstNode::Directive(Directive::BadRedirect { problem, .. }) = &node.inner {
+                self.report(node.span, problem.clone());
+            }
+
+            for bet in node.inner.bets() {
+                self.check_bet(bet, page.as_deref());
+            }
+            if let Some(children) = node.inner.children() {
+                self.check_nodes(children, page.as_deref());
+            }
+        }
+    }
+}
+
+/// Find internal links that do not go anywhere, and pages or
+/// sections that are defined twice.
+pub fn check_links(ast: &PassedSyntaxTree) -> Vec<Diagnostic> {
+    let mut checker = LinkChecker {
+        index: route_index(ast),
+        pages: HashSet::new(),
+        sections: HashSet::new(),
+        diagnostics: vec![],
+    };
+    checker.check_nodes(ast, None);
+    checker.diagnostics
+}
+
+/// [`check_links`] and [`check_footnotes`] over a tree from
+/// [`crate::parse::parse_n_pass_pages`], whose first `main_len` nodes are the main file. The
+/// diagnostics are per file, like the ones from parsing, since their spans only mean something
+/// together with the file.
+pub fn check_files(ast: &PassedSyntaxTree, main_len: usize) -> Result<Vec<Vec<Diagnostic>>> {
+    if main_len > ast.len() {
+        bail!("main_len {main_len} is past the end of the tree");
+    }
+
+    let mut checker = LinkChecker {
+        index: route_index(ast),
+        pages: HashSet::new(),
+        sections: HashSet::new(),
+        diagnostics: vec![],
+    };
+    let (main, pages) = ast.split_at(main_len);
+    Ok(iter::once(main)
+        .chain(pages.chunks(1))
+        .map(|nodes| {
+            checker.check_nodes(nodes, None);
+            let mut diagnostics = mem::take(&mut checker.diagnostics);
+            diagnostics.extend(check_footnotes(nodes));
+            diagnostics
+        })
+        .collect())
+}
+
+/// Find footnote references without a definition on their page, and
+/// definitions that nothing refers to.
+pub fn check_footnotes(ast: &[BackrefAstNode]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    check_footnote_nodes(ast, None, &mut diagnostics);
+    diagnostics
+}
+
+/// `numbered` holds the labels pass4 numbered on the current page, and is `None` outside of pages.
+fn check_footnote_nodes(
+    nodes: &[BackrefAstNode],
+    numbered: Option<&HashSet<String>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for node in nodes {
+        let page_numbered: HashSet<String>;
+        let mut numbered = numbered;
+        if let AstNode::Heading {
+            routing: Some(Route::Page(_)),
+            children,
+            ..
+        } = &node.inner
+        {
+            page_numbered = children
+                .iter()
+                .filter_map(|child| match &child.inner {
+                    AstNode::Footnotes(footnotes) => Some(footnotes),
+                    _ => None,
+                })
+                .flatten()
+                .filter_map(|footnote| footnote.label.clone())
+                .collect();
+            numbered = Some(&page_numbered);
+        }
+
+        if let Some(numbered) = numbered {
+            if let AstNode::FootnoteDefinition { label, .. } = &node.inner {
+                if !numbered.contains(label) {
+                    diagnostics.push(Diagnostic {
+                        span: node.span,
+                        message: format!("footnote {:?} is never referenced", label),
+                        expected: vec![],
+                    });
+                }
+            }
+            for bet in node.inner.bets() {
+                check_footnote_refs(bet, diagnostics);
+            }
+        }
+        if let Some(children) = node.inner.children() {
+            check_footnote_nodes(children, numbered, diagnostics);
+        }
+    }
+}
+
+fn check_footnote_refs(bet: &BlockExprTree, diagnostics: &mut Vec<Diagnostic>) {
+    for ben in bet {
+        if let BlockExprNode::FootnoteRef {
+            label: Some(label),
+            number: None,
+            ..
+        } = &ben.inner
+        {
+            diagnostics.push(Diagnostic {
+                span: ben.span,
+                message: format!("footnote {:?} is not defined on this page", label),
+                expected: vec![],
+            });
+        }
+        if let Some(children) = ben.inner.children() {
+            check_footnote_refs(children, diagnostics);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_n_pass_pages, PageFile};
+
+    /// The diagnostics of each file, as `line:column: message`
+    fn check(main: &str, pages: &[PageFile]) -> Vec<Vec<String>> {
+        let parsed = parse_n_pass_pages(main, pages).unwrap();
+        assert!(
+            parsed.diagnostics.iter().all(Vec::is_empty),
+            "{:?}",
+            parsed.diagnostics
+        );
+        check_files(&parsed.ast, parsed.main_len)
+            .unwrap()
+            .iter()
+            .map(|diagnostics| diagnostics.iter().map(ToString::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn diagnostics_go_with_their_file() {
+        let main = "* home :index:\n[[/about][about]] [[/nowhere][nowhere]]\n";
+        let about = "#+TITLE: About me\n\nsee [[/gone][this]]\n";
+        let pages = [PageFile {
+            name: "about.org",
+            source: about,
+        }];
+        assert_eq!(
+            check(main, &pages),
+            vec![
+                vec![r#"2:19: no page "nowhere" for link "/nowhere""#],
+                vec![r#"3:5: no page "gone" for link "/gone""#],
+            ]
+        );
+    }
+
+    #[test]
+    fn page_headings_of_files_point_at_their_keywords() {
+        let main = "* about :about:\n";
+        let about = "# comment\n#+ID: about\n#+TITLE: About [[/gone][me]]\n";
+        let pages = [PageFile {
+            name: "about-me.org",
+            source: about,
+        }];
+        assert_eq!(
+            check(main, &pages),
+            vec![
+                vec![],
+                vec![
+                    r#"3:1: page "about" is defined more than once"#,
+                    r#"3:16: no page "gone" for link "/gone""#,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn main_len_has_to_fit() {
+        let ast = crate::parse::parse_n_pass("* one :one:\n").unwrap();
+        assert_eq!(check_files(&ast, 1).unwrap().len(), 1);
+        assert!(check_files(&ast, 2).is_err());
+    }
+
+    #[test]
+    fn bad_redirects_are_reported() {
+        let main = "* old :old:\n#+REDIRECT: javascript:alert(1)\n";
+        assert_eq!(
+            check(main, &[]),
+            vec![vec![
+                r#"2:1: redirect target "javascript:alert(1)" would run code"#
+            ]]
+        );
+    }
+
+    #[test]
+    fn links_to_what_the_server_serves_are_fine() {
+        let main = concat!(
+            "* home :index:\n",
+            "* other :other:\n",
+            "[[/][home]] [[/feed.xml][feed]] [[/robots.txt?x][robots]] [[/static/a.png]] ",
+            "[[/image-cache/a-480w.png]] [[/static]] [[/feed]]\n",
+        );
+        assert_eq!(
+            check(main, &[]),
+            vec![vec![
+                r#"3:105: no page "static" for link "/static""#,
+                r#"3:117: no page "feed" for link "/feed""#,
+            ]]
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//!
+//! JSON output for tools that want the parsed tree. The layout is documented in the README;
+//! bump [`SCHEMA_VERSION`] whenever a change to the data types changes it.
+//!
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{check::Diagnostic, parse::BackrefAstNode};
+
+pub const SCHEMA_VERSION: u32 = 13;
+
+#[derive(Debug, Serialize)]
+pub struct JsonDocument<'a> {
+    pub schema_version: u32,
+    pub nodes: &'a [BackrefAstNode],
+}
+
+pub fn to_json(nodes: &[BackrefAstNode]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&JsonDocument {
+        schema_version: SCHEMA_VERSION,
+        nodes,
+    })?)
+}
+
+/// What `--check-links --format json` prints.
+#[derive(Debug, Serialize)]
+pub struct JsonDiagnostics<'a> {
+    pub schema_version: u32,
+    pub file: &'a str,
+    pub diagnostics: &'a [Diagnostic],
+}
+
+pub fn diagnostics_to_json(file: &str, diagnostics: &[Diagnostic]) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&JsonDiagnostics {
+        schema_version: SCHEMA_VERSION,
+        file,
+        diagnostics,
+    })?)
+}
@@ -1,3 +1,6 @@
 #![feature(iter_intersperse)]
+pub mod access;
+pub mod check;
+pub mod json;
 pub mod parse;
 pub mod treewalk;
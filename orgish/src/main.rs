@@ -3,13 +3,21 @@ use std::{
     env,
     fs::File,
     io::{self, Read},
+    process,
 };
 
-use orgish::{parse::{parse_n_pass, OutputTo}, treewalk::ast_to_html_string};
+use orgish::{
+    check::{check_footnotes, check_links},
+    json::{diagnostics_to_json, to_json},
+    parse::{parse_n_pass, parse_n_pass_recovering, OutputTo},
+    treewalk::{ast_to_html_string, ImageInfos},
+};
 
 fn main() -> Result<()> {
     let mut args = env::args().into_iter().peekable();
     let mut print_ast = false;
+    let mut check = false;
+    let mut json = false;
     let mut maybe_file: Option<String> = None;
 
     // index 0 is undefined
@@ -20,27 +28,68 @@ fn main() -> Result<()> {
             "--print-ast" => {
                 print_ast = true;
             }
+            "--check-links" => {
+                check = true;
+            }
+            "--format" => match args.next().as_deref() {
+                Some("json") => json = true,
+                Some("html") => json = false,
+                Some(other) => usage(&format!("unknown format: {}", other)),
+                None => usage("--format needs html or json"),
+            },
             file if args.peek().is_none() => {
                 maybe_file = Some(file.to_string());
             }
-            arg => panic!("unknown arg: {}", arg),
+            arg => usage(&format!("unknown arg: {}", arg)),
         }
     }
 
     let buf = Box::leak(Box::new(String::new()));
-    match maybe_file {
+    match &maybe_file {
         Some(file) => File::open(file)?.read_to_string(buf)?,
         None => io::stdin().read_to_string(buf)?,
     };
 
+    if check {
+        // Report every parse error along with the links, not just the first one.
+        let (ast, mut diagnostics) = parse_n_pass_recovering(buf)?;
+        if print_ast {
+            eprintln!("{:#?}", ast);
+        }
+        diagnostics.append(&mut check_links(&ast));
+        diagnostics.append(&mut check_footnotes(&ast));
+        let file = maybe_file.as_deref().unwrap_or("<stdin>");
+        if json {
+            println!("{}", diagnostics_to_json(file, &diagnostics)?);
+        } else {
+            for diagnostic in &diagnostics {
+                eprintln!("{}:{}", file, diagnostic);
+            }
+        }
+        // Something for CI to fail on.
+        process::exit(if diagnostics.is_empty() { 0 } else { 1 });
+    }
+
     let ast = parse_n_pass(buf)?;
     if print_ast {
         eprintln!("{:#?}", ast);
     }
 
-    let bufs = ast_to_html_string(&ast, OutputTo::Main)?;
+    if json {
+        println!("{}", to_json(&ast)?);
+        return Ok(());
+    }
+
+    // Nothing to look images up in from here.
+    let bufs = ast_to_html_string(&ast, OutputTo::Main, &ImageInfos::new())?;
 
     println!("{:#?}", bufs);
 
     Ok(())
 }
+
+fn usage(problem: &str) -> ! {
+    eprintln!("orgish: {}", problem);
+    eprintln!("usage: orgish [--print-ast] [--check-links] [--format html|json] [FILE]");
+    process::exit(2);
+}
@@ -1,27 +1,92 @@
-use std::fmt::Display;
+use std::{fmt::Display, iter};
 
 use combine::{
-    attempt, between, choice, many1, opaque, optional,
-    parser::char::{alpha_num, newline},
+    attempt, between, choice, eof,
+    error::StreamError,
+    look_ahead, many1, not_followed_by, opaque, optional,
+    parser::char::{alpha_num, digit, newline},
     parser::{
         char::string,
         combinator::{no_partial, FnOpaque},
         repeat::{many, take_until},
-        token::token,
+        token::{token, tokens},
     },
     position, satisfy, skip_many,
-    stream::position,
+    stream::{
+        easy,
+        position::{self, Positioner, RangePositioner},
+        StreamErrorFor,
+    },
     EasyParser, ParseError, Parser, Stream, StreamOnce,
 };
 
 use super::{
     data::{AstNode, BlockExprNode},
-    AbstractSyntaxTree, BlockExprTree, BlockType, Directive, LinkTarget, RenderGroup, Route,
+    stringify_bet, AbstractSyntaxTree, BackrefAstNode, BlockExprTree, BlockType, Checkbox,
+    ColumnAlignment, Diagnostic, Directive, LinkTarget, ListBullet, RenderGroup, Route, SourcePos,
+    Span, Spanned, TableRow,
 };
 
+impl Positioner<char> for SourcePos {
+    type Position = SourcePos;
+    type Checkpoint = SourcePos;
+
+    fn position(&self) -> SourcePos {
+        *self
+    }
+
+    fn update(&mut self, token: &char) {
+        self.offset += token.len_utf8();
+        if *token == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+
+    fn checkpoint(&self) -> SourcePos {
+        *self
+    }
+
+    fn reset(&mut self, checkpoint: SourcePos) {
+        *self = checkpoint;
+    }
+}
+
+impl<'a> RangePositioner<char, &'a str> for SourcePos {
+    fn update_range(&mut self, range: &&'a str) {
+        for c in range.chars() {
+            self.update(&c);
+        }
+    }
+}
+
+/// Records where `parser` started and stopped.
+fn spanned<Input, P>(parser: P) -> impl Parser<Input, Output = Spanned<P::Output>>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    P: Parser<Input>,
+{
+    (position(), parser, position())
+        .map(|(start, inner, end)| Spanned::new(inner, Span { start, end }))
+}
+
+/// `s` as plain characters, starting at `pos`.
+fn plain_chars(s: &str, mut pos: SourcePos) -> BlockExprTree {
+    s.chars()
+        .map(|c| {
+            let start = pos;
+            pos.update(&c);
+            Spanned::new(BlockExprNode::Char(c), Span { start, end: pos })
+        })
+        .collect()
+}
+
 fn whitespace<Input>() -> impl Parser<Input, Output = char>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -30,7 +95,7 @@ where
 
 fn whitespaces<Input>() -> impl Parser<Input, Output = String>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -39,7 +104,7 @@ where
 
 fn linespace<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -47,30 +112,35 @@ where
         .map(|_| ())
         .expected("a comment");
     let skipline = newline().map(|_| ());
-    skipline
-        .or(attempt(comment))
-        .map(|_| AstNode::Block((BlockType::Inline, vec![BlockExprNode::Linespace])))
+    spanned(skipline.or(attempt(comment)))
+        .map(|ls| {
+            AstNode::Block((
+                BlockType::Inline,
+                vec![Spanned::new(BlockExprNode::Linespace, ls.span)],
+            ))
+        })
         .message("while parsing linespace")
 }
 
 fn directive<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
     (
         between(string("#+"), token(':'), many(satisfy(|c| c != ':'))),
         whitespaces(),
+        position(),
         many1(satisfy(|c: char| !c.is_control())),
     )
-        .map(|(key, _, value)| AstNode::Directive(Directive::Raw(key, value)))
+        .map(|(key, _, pos, value)| AstNode::Directive(Directive::Raw(key, value, pos)))
         .message("while parsing directive")
 }
 
 fn source_block<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -89,9 +159,208 @@ where
         .message("while parsing source block")
 }
 
+/// `#+BEGIN_QUOTE`, `#+BEGIN_EXAMPLE`, `#+BEGIN_VERSE`, `#+BEGIN_CENTER`, `#+BEGIN_COMMENT` and
+/// `#+BEGIN_EXPORT backend`, in either case. Anything else after the block name is ignored.
+/// Unknown and unterminated blocks fail, and end up as text like they always have.
+fn greater_block<Input>() -> impl Parser<Input, Output = AstNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    let line = || {
+        (
+            position(),
+            many::<String, _, _>(satisfy(|c| c != '\n')),
+            newline(),
+        )
+            .map(|(pos, line, _)| (pos, line))
+    };
+
+    (
+        attempt(string("#+BEGIN_")).or(string("#+begin_")),
+        many1::<String, _, _>(alpha_num()),
+        many::<String, _, _>(satisfy(|c| c != '\n')),
+        newline(),
+        position(),
+    )
+        .then(move |(_, name, params, _, content_pos)| {
+            let end = format!("#+END_{}", name.to_uppercase());
+            let is_end = move |line: &str| line.trim().to_uppercase() == end;
+            let is_end_2 = is_end.clone();
+            (
+                many::<Vec<_>, _, _>(attempt(line().and_then(move |(pos, line)| {
+                    if is_end(&line) {
+                        Err(StreamErrorFor::<Input>::unexpected_static_message(
+                            "the end of the block",
+                        ))
+                    } else {
+                        Ok((pos, line))
+                    }
+                }))),
+                // The newline after the end is left to the linespace after this statement.
+                many::<String, _, _>(satisfy(|c| c != '\n')).and_then(move |line| {
+                    if is_end_2(&line) {
+                        Ok(())
+                    } else {
+                        Err(StreamErrorFor::<Input>::expected_static_message(
+                            "the end of the block",
+                        ))
+                    }
+                }),
+            )
+                .map(move |(lines, _)| (name.to_uppercase(), params.clone(), content_pos, lines))
+        })
+        .flat_map(|(name, params, content_pos, lines)| {
+            let content = lines
+                .iter()
+                .map(|(_, line)| format!("{line}\n"))
+                .collect::<String>();
+            // The children are parsed on their own, starting where they are in the outer
+            // document. If they don't parse, neither does the block.
+            let children = || match org_file()
+                .easy_parse(position::Stream::with_positioner(&content[..], content_pos))
+            {
+                Ok((nodes, rest)) if rest.input.is_empty() => {
+                    Ok(nodes.into_iter().map(BackrefAstNode::new_unref).collect())
+                }
+                Ok((_, rest)) => Err(Input::Error::from_error(
+                    rest.positioner,
+                    StreamErrorFor::<Input>::message_format(format!(
+                        "in {name} block: unexpected {:?}",
+                        rest.input
+                    )),
+                )),
+                Err(err) => Err(Input::Error::from_error(
+                    err.position,
+                    StreamErrorFor::<Input>::message_format(format!(
+                        "in {name} block: {}",
+                        describe_errors(&err.errors).0
+                    )),
+                )),
+            };
+
+            Ok(match &name[..] {
+                "QUOTE" => AstNode::Quote(children()?),
+                "CENTER" => AstNode::Center(children()?),
+                "VERSE" => AstNode::Verse(
+                    lines
+                        .iter()
+                        .map(|(pos, line)| subparse_bet(line, *pos))
+                        .collect(),
+                ),
+                "EXAMPLE" => AstNode::Example(
+                    lines
+                        .iter()
+                        // `,*` and `,#+` are how lines that would mean something else are escaped.
+                        .map(|(_, line)| match line.trim_start().strip_prefix(',') {
+                            Some(rest) if rest.starts_with('*') || rest.starts_with("#+") => {
+                                format!("{}{rest}\n", &line[..line.len() - line.trim_start().len()])
+                            }
+                            _ => format!("{line}\n"),
+                        })
+                        .collect(),
+                ),
+                "EXPORT" => AstNode::Export {
+                    backend: params
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_lowercase(),
+                    content,
+                },
+                // Dropped like `# comments`
+                "COMMENT" => AstNode::Block((
+                    BlockType::Inline,
+                    vec![Spanned::new(
+                        BlockExprNode::Linespace,
+                        Span {
+                            start: content_pos,
+                            end: content_pos,
+                        },
+                    )],
+                )),
+                _ => {
+                    return Err(Input::Error::from_error(
+                        content_pos,
+                        StreamErrorFor::<Input>::message_format(format!("unknown block {name}")),
+                    ))
+                }
+            })
+        })
+        .message("while parsing greater block")
+}
+
+/// Display math that may go over several lines: `\[…\]`, `$$…$$` or a LaTeX environment like
+/// `\begin{align}…\end{align}`, which is kept whole. Blank lines end paragraphs, and math too.
+fn display_math<Input>() -> impl Parser<Input, Output = AstNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    let open = choice!(
+        attempt(string("\\[")).map(|_| (None, "\\]".to_string())),
+        string("$$").map(|_| (None, "$$".to_string())),
+        (
+            string("\\begin{"),
+            many1::<String, _, _>(satisfy(|c| c != '}' && c != '\n')),
+            token('}'),
+        )
+            .map(|(_, name, _)| {
+                (
+                    Some(format!("\\begin{{{name}}}")),
+                    format!("\\end{{{name}}}"),
+                )
+            })
+    );
+
+    spanned(
+        open.then(|(begin, end): (Option<String>, String)| {
+            let close = end.chars().collect::<Vec<_>>();
+            let close_2 = close.clone();
+            (
+                take_until::<String, _, _>(attempt(tokens(
+                    |l, r| l == r,
+                    "the end of the math",
+                    close,
+                ))),
+                tokens(|l, r| l == r, "the end of the math", close_2),
+            )
+                .map(move |(inner, _)| match &begin {
+                    Some(begin) => format!("{begin}{inner}{end}"),
+                    None => inner,
+                })
+        })
+        .and_then(|latex: String| {
+            // Only lines in between can be blank, the first and last one have the delimiters on
+            // them. Math on a single line has no lines in between.
+            let lines = latex.split('\n').collect::<Vec<_>>();
+            if latex.trim().is_empty()
+                || lines
+                    .iter()
+                    .skip(1)
+                    .take(lines.len().saturating_sub(2))
+                    .any(|line| line.trim().is_empty())
+            {
+                Err(StreamErrorFor::<Input>::unexpected_static_message(
+                    "blank line in math",
+                ))
+            } else {
+                Ok(BlockExprNode::Math {
+                    display: true,
+                    latex,
+                })
+            }
+        }),
+    )
+    .map(|math| AstNode::Block((BlockType::Block, vec![math])))
+    .message("while parsing display math")
+}
+
 fn horiz_rule<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -100,16 +369,152 @@ where
         .message("while parsing horiz_rule")
 }
 
-fn block_expr_node<Input>() -> FnOpaque<Input, BlockExprNode>
+/// Pre-pass datatype for `table`, before the header is split off.
+enum RawTableRow {
+    Rule,
+    Cells(Vec<(SourcePos, String)>),
+}
+
+fn table<Input>() -> impl Parser<Input, Output = AstNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    // Only `|---+---|` and the like, `|-5 | x|` is data.
+    let rule = (
+        token('-'),
+        skip_many(satisfy(|c: char| {
+            matches!(c, '-' | '+' | '|') || (c.is_whitespace() && c != '\n')
+        })),
+        look_ahead(newline().map(|_| ()).or(eof())),
+    )
+        .map(|_| RawTableRow::Rule);
+    let cell = || {
+        attempt((
+            position(),
+            take_until::<String, _, _>(choice((token('|'), newline()))),
+            token('|'),
+        ))
+        .map(|(pos, s, _)| (pos, s))
+    };
+    let cells = (
+        many::<Vec<_>, _, _>(cell()),
+        position(),
+        many::<String, _, _>(satisfy(|c| c != '\n')),
+    )
+        .map(|(mut cells, pos, rest)| {
+            // | a | b  <- the last cell does not need a closing pipe
+            if !rest.trim().is_empty() {
+                cells.push((pos, rest));
+            }
+            RawTableRow::Cells(cells)
+        });
+    let row = attempt((
+        whitespaces(),
+        token('|'),
+        choice((attempt(rule), cells)),
+        newline().map(|_| ()).or(eof()),
+    ))
+    .map(|(_, _, row, _)| row);
+
+    many1::<Vec<_>, _, _>(row)
+        .map(raw_rows_to_table)
+        .message("while parsing table")
+}
+
+fn raw_rows_to_table(rows: Vec<RawTableRow>) -> AstNode {
+    fn alignment_cookie(cell: &str) -> Option<Option<ColumnAlignment>> {
+        let inner = cell.trim().strip_prefix('<')?.strip_suffix('>')?;
+        let (align, width) = inner.split_at(inner.len().min(1));
+        let align = match align {
+            "l" => Some(ColumnAlignment::Left),
+            "r" => Some(ColumnAlignment::Right),
+            "c" => Some(ColumnAlignment::Center),
+            // <10> only sets the width, which we don't care about
+            _ if inner.chars().all(|c| c.is_ascii_digit()) => None,
+            _ => return None,
+        };
+        width.chars().all(|c| c.is_ascii_digit()).then(|| align)
+    }
+
+    let mut alignments = None;
+    let mut groups: Vec<Vec<TableRow>> = vec![vec![]];
+    for row in rows {
+        match row {
+            RawTableRow::Rule => groups.push(vec![]),
+            RawTableRow::Cells(cells) => {
+                let is_cookie_row = cells.iter().any(|(_, c)| !c.trim().is_empty())
+                    && cells
+                        .iter()
+                        .all(|(_, c)| c.trim().is_empty() || alignment_cookie(c).is_some());
+                if is_cookie_row {
+                    // The first cookie row wins, like upstream org.
+                    alignments.get_or_insert_with(|| {
+                        cells
+                            .iter()
+                            .map(|(_, c)| alignment_cookie(c).flatten())
+                            .collect::<Vec<_>>()
+                    });
+                } else {
+                    groups.last_mut().expect("groups to never be empty").push(
+                        cells
+                            .iter()
+                            .map(|(pos, c)| {
+                                let trimmed = c.trim_start();
+                                let mut pos = *pos;
+                                pos.update_range(&&c[..c.len() - trimmed.len()]);
+                                subparse_bet(trimmed.trim_end(), pos)
+                            })
+                            .collect(),
+                    );
+                }
+            }
+        }
+    }
+
+    groups.retain(|g| !g.is_empty());
+    // Rows above the first rule are the header, but only if there is something below it.
+    let header = if groups.len() > 1 {
+        groups.remove(0)
+    } else {
+        vec![]
+    };
+
+    AstNode::Table {
+        header,
+        body: groups.into_iter().flatten().collect(),
+        alignments: alignments.unwrap_or_default(),
+    }
+}
+
+/// Re-parse a string we already took out of the stream into a BET.
+/// `pos` is where `s` starts in the source, so the spans stay correct.
+/// If that fails, the string is kept as plain characters.
+pub fn subparse_bet(s: &str, pos: SourcePos) -> BlockExprTree {
+    many::<BlockExprTree, _, _>(block_expr_node())
+        .easy_parse(position::Stream::with_positioner(s, pos))
+        .map(|(mut bet, rest)| {
+            // anything block_expr_node refused, e.g. control characters
+            bet.extend(plain_chars(rest.input, rest.positioner));
+            bet
+        })
+        .unwrap_or_else(|_| plain_chars(s, pos))
+}
+
+fn block_expr_node<Input>() -> FnOpaque<Input, Spanned<BlockExprNode>>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
     opaque!(no_partial(
-        choice!(
+        spanned(choice!(
             attempt(link()),
+            attempt(footnote_ref()),
+            attempt(export_snippet()),
             attempt(inline_code()),
+            attempt(math()),
             attempt(nbsp()),
             attempt(float_toggle()),
             attempt(warning()),
@@ -118,14 +523,14 @@ where
             attempt(underline()),
             attempt(strikethrough()),
             char()
-        )
+        ))
         .message("while parsing block_expr_node")
     ))
 }
 
 fn ast_block_expr_node<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -134,7 +539,7 @@ where
 
 fn char<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -145,7 +550,7 @@ where
 
 fn marker_char<Input>(ch: char) -> impl Parser<Input, Output = BlockExprTree>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -159,7 +564,7 @@ fn marker_chars<Input, P: Parser<Input>>(
     end: Box<dyn Fn() -> P>,
 ) -> impl Parser<Input, Output = BlockExprTree>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -170,14 +575,28 @@ where
         start,
         (position(), take_until::<String, _, _>(end_1)).flat_map(|(pos, s)| {
             // HACK ouch ouch ouch
-            Ok(many1(block_expr_node())
-                .easy_parse(position::Stream::new(&s[..]))
-                // this is the except on Result
-                // TODO it PANICs. Make it not.
-                .map_err(|e| format!("{}", e))
-                .unwrap_or_else(|e| panic!(r#"In marker_char subparser "{s}" @ {}
-{e}"#, pos))
-                .0)
+            // The sub-stream starts at `pos`, so its error positions are already in the outer
+            // document. Failing here makes the caller's attempt() back off, and the markers end
+            // up as plain characters.
+            let sub_error = |pos, message: String| {
+                Input::Error::from_error(
+                    pos,
+                    StreamErrorFor::<Input>::message_format(format!(
+                        "in marker_chars {:?}: {}",
+                        s, message
+                    )),
+                )
+            };
+            match many1::<BlockExprTree, _, _>(block_expr_node())
+                .easy_parse(position::Stream::with_positioner(&s[..], pos))
+            {
+                Ok((bet, rest)) if rest.input.is_empty() => Ok(bet),
+                Ok((_, rest)) => Err(sub_error(
+                    rest.positioner,
+                    format!("unexpected {:?}", rest.input),
+                )),
+                Err(err) => Err(sub_error(err.position, describe_errors(&err.errors).0)),
+            }
         }),
         end_2,
     )
@@ -185,9 +604,82 @@ where
         .message("while parsing marker_chars")
 }
 
+/// `@@html:<b>raw</b>@@`
+fn export_snippet<Input>() -> impl Parser<Input, Output = BlockExprNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    (
+        string("@@"),
+        many1::<String, _, _>(satisfy(|c: char| c.is_alphanumeric() || c == '-')),
+        token(':'),
+        take_until::<String, _, _>(attempt(string("@@"))),
+        string("@@"),
+    )
+        .map(|(_, backend, _, content, _)| BlockExprNode::Export {
+            backend: backend.to_lowercase(),
+            content,
+        })
+        .message("while parsing export_snippet")
+}
+
+/// `$x^2$`, `$$x^2$$`, `\(x^2\)` and `\[x^2\]`, on one line. Like in Org, a single `$` has to
+/// hug what's inside and can't be followed by a letter or digit, so "$5 or $10" stays text.
+fn math<Input>() -> impl Parser<Input, Output = BlockExprNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    let not_empty = |latex: String, display| {
+        if latex.trim().is_empty() {
+            Err(StreamErrorFor::<Input>::unexpected_static_message(
+                "empty math",
+            ))
+        } else {
+            Ok(BlockExprNode::Math { display, latex })
+        }
+    };
+    let delimited = move |open: &'static str, close: &'static str, display| {
+        (
+            string(open),
+            take_until::<String, _, _>(
+                attempt(string(close)).map(|_| ()).or(newline().map(|_| ())),
+            ),
+            string(close),
+        )
+            .and_then(move |(_, latex, _)| not_empty(latex, display))
+    };
+    let dollar = (
+        token('$'),
+        many1::<String, _, _>(satisfy(|c| c != '$' && c != '\n')),
+        token('$'),
+        not_followed_by(satisfy(char::is_alphanumeric)),
+    )
+        .and_then(move |(_, latex, _, _)| {
+            if latex.starts_with(char::is_whitespace) || latex.ends_with(char::is_whitespace) {
+                Err(StreamErrorFor::<Input>::unexpected_static_message(
+                    "space inside of $",
+                ))
+            } else {
+                not_empty(latex, false)
+            }
+        });
+
+    choice!(
+        attempt(delimited("$$", "$$", true)),
+        attempt(delimited("\\(", "\\)", false)),
+        attempt(delimited("\\[", "\\]", true)),
+        dollar
+    )
+    .message("while parsing math")
+}
+
 fn inline_code<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -206,7 +698,7 @@ where
 // This one is excluded from the BEN choice! since it's only valid in header title context.
 fn header_routing<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -218,7 +710,7 @@ where
 // this one parses the whole :thing: because that avoids another painful subparse
 fn internal_routing<Input>() -> impl Parser<Input, Output = Route>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -236,7 +728,7 @@ where
 
 fn bold<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -247,7 +739,7 @@ where
 
 fn italic<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -258,7 +750,7 @@ where
 
 fn underline<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -269,7 +761,7 @@ where
 
 fn strikethrough<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -280,7 +772,7 @@ where
 
 fn nbsp<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -291,7 +783,7 @@ where
 
 fn float_toggle<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -303,7 +795,7 @@ where
 
 fn warning<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -315,7 +807,7 @@ where
 
 fn link<Input>() -> impl Parser<Input, Output = BlockExprNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -333,7 +825,14 @@ where
         .map(|(_, _, internal, link, _, maybe_bet, _)| {
             BlockExprNode::Link(
                 if internal.is_some() {
-                    LinkTarget::Heading { title: link }
+                    // Headings are matched on their plain text, so [[*a *b*]] finds "a b".
+                    LinkTarget::Heading {
+                        title: stringify_bet(&subparse_bet(&link, SourcePos::default()))
+                            .unwrap_or(link)
+                            .split_whitespace()
+                            .intersperse(" ")
+                            .collect(),
+                    }
                 } else {
                     LinkTarget::External(link)
                 },
@@ -343,9 +842,94 @@ where
         .message("while parsing link")
 }
 
+fn footnote_label<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    many1(satisfy(|c: char| {
+        c.is_alphanumeric() || c == '-' || c == '_'
+    }))
+}
+
+fn footnote_ref<Input>() -> impl Parser<Input, Output = BlockExprNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    (
+        string("[fn:"),
+        optional(footnote_label()), // name
+        optional(
+            (
+                token(':'),
+                whitespaces(),
+                // Links eat their own brackets, so the first `]` out here ends the footnote.
+                many((not_followed_by(token(']')), block_expr_node()).map(|(_, ben)| ben)),
+            )
+                .map(|(_, _, bet)| bet), // : inline definition
+        ),
+        token(']'),
+    )
+        .and_then(|(_, label, definition, _)| match (label, definition) {
+            (None, None) => Err(StreamErrorFor::<Input>::message_static_message(
+                "a footnote needs a label or a definition",
+            )),
+            (label, definition) => Ok(BlockExprNode::FootnoteRef {
+                label,
+                definition,
+                number: None,
+            }),
+        })
+        .message("while parsing footnote_ref")
+}
+
+/// `[fn:label] definition`, which goes on over the next lines up to a blank line, a heading or
+/// the next definition, like in Org. The line breaks are kept as `\n` chars.
+fn footnote_definition<Input>() -> impl Parser<Input, Output = AstNode>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    let next_line = attempt(
+        (
+            spanned(newline()),
+            not_followed_by(choice!(
+                attempt((whitespaces(), newline())).map(|_| "a blank line"),
+                attempt((whitespaces(), token('*'))).map(|_| "a heading"),
+                string("[fn:").map(|_| "the next definition"),
+                eof().map(|_| "the end")
+            )),
+            many1::<Vec<_>, _, _>(block_expr_node()),
+        )
+            .map(|(newline, _, line)| {
+                iter::once(Spanned::new(BlockExprNode::Char('\n'), newline.span)).chain(line)
+            }),
+    );
+    (
+        string("[fn:"),
+        footnote_label(),
+        token(']'),
+        whitespaces(),
+        many::<Vec<_>, _, _>(block_expr_node()),
+        many::<Vec<_>, _, _>(next_line),
+    )
+        .map(|(_, label, _, _, first_line, next_lines)| {
+            let definition = first_line
+                .into_iter()
+                .chain(next_lines.into_iter().flatten())
+                .collect();
+            AstNode::FootnoteDefinition { label, definition }
+        })
+        .message("while parsing footnote_definition")
+}
+
 fn heading<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -353,7 +937,10 @@ where
         whitespaces(),
         many1::<Vec<_>, _, _>(token('*')).map(|x: Vec<_>| x.len()),
         whitespaces(),
-        many1(choice!(attempt(header_routing()), block_expr_node())),
+        many1(choice!(
+            attempt(spanned(header_routing())),
+            block_expr_node()
+        )),
     )
         .map(|(_, level, _, title)| AstNode::Heading {
             level: level
@@ -362,44 +949,108 @@ where
             title,
             children: vec![], // we fill this in later
             routing: None,    // this is processed in the second pass (./pass.rs)
+            anchor: None,
         })
         .message("while parsing heading")
 }
 
 fn list_item<Input>() -> impl Parser<Input, Output = AstNode>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
     // Upstream org also supports * but I don't need that.
+    let bullet = choice!(
+        choice!(token('-'), token('+')).map(ListBullet::Unordered),
+        (
+            many1::<String, _, _>(digit()),
+            choice!(token('.'), token(')'))
+        )
+            // A number too big for a counter is just text.
+            .and_then(|(n, delim)| match n.parse() {
+                Ok(n) => Ok(ListBullet::Ordered(n, delim)),
+                Err(_) => Err(StreamErrorFor::<Input>::message_static_message(
+                    "a list number that fits in 32 bits",
+                )),
+            })
+    );
+
+    let checkbox = attempt(
+        (
+            token('['),
+            choice!(
+                token(' ').map(|_| Checkbox::Off),
+                token('X').or(token('x')).map(|_| Checkbox::On),
+                token('-').map(|_| Checkbox::Partial)
+            ),
+            token(']'),
+            whitespaces(),
+        )
+            .map(|(_, cb, _, _)| cb),
+    );
+
+    // - term :: description
+    let term = attempt(
+        (
+            position(),
+            many1::<String, _, _>(attempt(
+                (not_followed_by(string(" ::")), satisfy(|c| c != '\n')).map(|(_, c)| c),
+            )),
+            string(" ::"),
+            choice!(
+                whitespace().map(|_| ()),
+                look_ahead(newline()).map(|_| ()),
+                eof()
+            ),
+        )
+            .map(|(pos, term, _, _)| subparse_bet(&term, pos)),
+    );
+
     // Like `heading`, the children are added in the next pass.
-    (whitespaces(), choice!(token('-'), token('+')), whitespace())
-        .map(|(ws, _, _)| AstNode::ListItem(ws.len() as u16, Vec::new()))
+    (
+        whitespaces(),
+        bullet,
+        whitespace(),
+        optional(checkbox),
+        optional(term),
+    )
+        .map(|(ws, bullet, _, checkbox, term)| AstNode::ListItem {
+            indent: ws.len() as u16,
+            bullet,
+            checkbox,
+            term,
+            children: Vec::new(),
+        })
+        .message("while parsing list item")
 }
 
-pub fn ast_node<Input>() -> impl Parser<Input, Output = AstNode>
+pub fn ast_node<Input>() -> impl Parser<Input, Output = Spanned<AstNode>>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
-    macro_rules! stmt { ($e:expr) => { (attempt($e), linespace()).map(|(a, _)| a) }; }
-    macro_rules! expr { ($e:expr) => { attempt($e) }; }
+    macro_rules! stmt { ($e:expr) => { (attempt(spanned($e)), linespace()).map(|(a, _)| a) }; }
+    macro_rules! expr { ($e:expr) => { attempt(spanned($e)) }; }
 
     choice!(
         stmt!(heading()),
         stmt!(source_block()),
+        stmt!(greater_block()),
+        stmt!(display_math()),
         stmt!(directive()),
         stmt!(horiz_rule()), // (BEN link needs to be parsed before ASN horiz_rule, only works with stmt!'s attempt)
+        expr!(table()),      // eats its own newlines since it spans multiple lines
         expr!(list_item()),
+        stmt!(footnote_definition()),
         stmt!(ast_block_expr_node())
     )
 }
 
 pub fn org_file<Input>() -> impl Parser<Input, Output = AbstractSyntaxTree>
 where
-    Input: Stream<Token = char>,
+    Input: Stream<Token = char, Position = SourcePos>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
     <Input as StreamOnce>::Position: Display,
 {
@@ -415,8 +1066,485 @@ where
     // |ast_node
     // |linespace
     // |ast_node
-    opaque!(no_partial(many::<Vec<_>, _, _>(
-        many1::<Vec<_>, _, _>(linespace()).or(ast_node().map(|n| vec![n]))
-    )
-    .map(|v| v.into_iter().flatten().collect::<Vec<_>>())))
+    opaque!(no_partial(
+        many::<Vec<_>, _, _>(org_item()).map(|v| v.into_iter().flatten().collect::<Vec<_>>())
+    ))
+}
+
+/// One [`ast_node`], or the linespace between two of them
+fn org_item<Input>() -> impl Parser<Input, Output = AbstractSyntaxTree>
+where
+    Input: Stream<Token = char, Position = SourcePos>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    <Input as StreamOnce>::Position: Display,
+{
+    many1::<Vec<_>, _, _>(spanned(linespace())).or(ast_node().map(|n| vec![n]))
+}
+
+/// Like [`org_file`], but a broken item doesn't end the parse. Everything from the start of the
+/// item to the end of the line the error is on becomes an [`AstNode::Error`], and parsing
+/// carries on from the next line.
+pub fn org_file_recovering(input: &str) -> (AbstractSyntaxTree, Vec<Diagnostic>) {
+    let mut stream = position::Stream::with_positioner(input, SourcePos::default());
+    let mut nodes = vec![];
+    let mut diagnostics = vec![];
+
+    while !stream.input.is_empty() {
+        let start = stream.positioner;
+        let (error_pos, message, expected) = match org_item().easy_parse(stream.clone()) {
+            Ok((items, rest)) if rest.positioner.offset > start.offset => {
+                nodes.extend(items);
+                stream = rest;
+                continue;
+            }
+            Ok(_) => (start, "nothing could be parsed here".to_string(), vec![]),
+            Err(err) => {
+                let (message, expected) = describe_errors(&err.errors);
+                (err.position, message, expected)
+            }
+        };
+
+        let line_end = input[error_pos.offset..]
+            .find('\n')
+            .map_or(input.len(), |idx| error_pos.offset + idx + 1);
+        let text = &input[start.offset..line_end];
+        stream.input = &input[line_end..];
+        stream.positioner.update_range(&text);
+        let end = stream.positioner;
+
+        nodes.push(Spanned::new(
+            AstNode::Error {
+                text: text.to_string(),
+                message: message.clone(),
+            },
+            Span { start, end },
+        ));
+        diagnostics.push(Diagnostic {
+            span: Span {
+                start: error_pos,
+                end,
+            },
+            message,
+            expected,
+        });
+    }
+
+    (nodes, diagnostics)
+}
+
+/// Parses `title` like the text of a heading, for titles that don't come from one, like a
+/// file's `#+TITLE:`. `start` is where `title` is in its file, so the spans point there.
+/// Titles the heading parser chokes on are kept as plain text.
+pub fn heading_title(title: &str, start: SourcePos) -> BlockExprTree {
+    let line = format!("* {title}");
+    // The `* ` isn't in the file, so the line starts two characters before `title` does.
+    let line_start = SourcePos {
+        offset: start.offset.saturating_sub(2),
+        column: start.column.saturating_sub(2),
+        ..start
+    };
+    let parsed = heading().easy_parse(position::Stream::with_positioner(&line[..], line_start));
+    match parsed {
+        Ok((AstNode::Heading { title, .. }, rest)) if rest.input.is_empty() => title,
+        _ => plain_chars(title, start),
+    }
+}
+
+/// Splits combine's errors into a message and the list of expected tokens.
+fn describe_errors(errors: &[easy::Error<char, &str>]) -> (String, Vec<String>) {
+    let info = |info: &easy::Info<char, &str>| match info {
+        easy::Info::Token(c) => format!("{:?}", c),
+        easy::Info::Range(r) => format!("{:?}", r),
+        other => other.to_string(),
+    };
+
+    let mut message = vec![];
+    let mut expected = vec![];
+    for error in errors {
+        match error {
+            easy::Error::Unexpected(i) => message.push(format!("unexpected {}", info(i))),
+            easy::Error::Message(i) => message.push(info(i)),
+            easy::Error::Other(err) => message.push(err.to_string()),
+            easy::Error::Expected(i) => {
+                let i = info(i);
+                if !expected.contains(&i) {
+                    expected.push(i)
+                }
+            }
+        }
+    }
+
+    if message.is_empty() {
+        message.push("could not parse this".to_string());
+    }
+    (message.join(", "), expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::is_linespace;
+
+    /// The top-level nodes of `input`, which has to parse all the way through
+    fn parse(input: &str) -> Vec<AstNode> {
+        let (nodes, rest) = org_file()
+            .easy_parse(position::Stream::with_positioner(
+                input,
+                SourcePos::default(),
+            ))
+            .unwrap();
+        assert_eq!(rest.input, "");
+        nodes.into_iter().map(|node| node.inner).collect()
+    }
+
+    fn text(bet: &BlockExprTree) -> String {
+        stringify_bet(bet).unwrap()
+    }
+
+    fn texts(rows: &[TableRow]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(text).collect())
+            .collect()
+    }
+
+    fn only_table(input: &str) -> (Vec<TableRow>, Vec<TableRow>, Vec<Option<ColumnAlignment>>) {
+        match &parse(input)[..] {
+            [AstNode::Table {
+                header,
+                body,
+                alignments,
+            }] => (header.clone(), body.clone(), alignments.clone()),
+            other => panic!("not a single table: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn table_with_header_and_alignment() {
+        let (header, body, alignments) = only_table(
+            "| Name | Qty |\n|------+-----|\n| <l> | <r5> |\n| *apple* | 3 |\n| pear | 10\n",
+        );
+        assert_eq!(texts(&header), vec![vec!["Name", "Qty"]]);
+        assert_eq!(texts(&body), vec![vec!["apple", "3"], vec!["pear", "10"]]);
+        assert_eq!(
+            alignments,
+            vec![Some(ColumnAlignment::Left), Some(ColumnAlignment::Right)]
+        );
+        // Cells are inline markup, not plain text.
+        assert!(matches!(body[0][0][0].inner, BlockExprNode::Bold(_)));
+    }
+
+    #[test]
+    fn cells_starting_with_a_dash_are_no_rule() {
+        let (header, body, _) = only_table("| a | b |\n|-5 | x|\n|--+--|\n");
+        assert!(header.is_empty());
+        assert_eq!(texts(&body), vec![vec!["a", "b"], vec!["-5", "x"]]);
+    }
+
+    #[test]
+    fn table_without_rule_has_no_header() {
+        let (header, body, alignments) = only_table("| a | b |\n| c | d |\n");
+        assert!(header.is_empty());
+        assert_eq!(texts(&body), vec![vec!["a", "b"], vec!["c", "d"]]);
+        assert!(alignments.is_empty());
+    }
+
+    #[test]
+    fn table_rule_at_the_end_makes_no_header() {
+        let (header, body, _) = only_table("| a | b |\n|---|\n");
+        assert!(header.is_empty());
+        assert_eq!(texts(&body), vec![vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn table_rows_keep_their_own_length() {
+        let (_, body, _) = only_table("| a | b | c |\n| d |\n|\n");
+        assert_eq!(
+            texts(&body),
+            vec![vec!["a", "b", "c"], vec!["d"], Vec::<&str>::new()]
+        );
+    }
+
+    #[test]
+    fn table_ends_at_the_first_line_without_a_pipe() {
+        let nodes = parse("  | a |\nafter\n");
+        assert!(matches!(&nodes[0], AstNode::Table { body, .. } if texts(body) == vec![vec!["a"]]));
+        assert!(matches!(&nodes[1], AstNode::Block((_, bet)) if text(bet) == "after"));
+    }
+
+    fn list_items(input: &str) -> Vec<(u16, ListBullet, Option<Checkbox>, Option<String>)> {
+        parse(input)
+            .into_iter()
+            .filter_map(|node| match node {
+                AstNode::ListItem {
+                    indent,
+                    bullet,
+                    checkbox,
+                    term,
+                    ..
+                } => Some((indent, bullet, checkbox, term.as_ref().map(text))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn list_item_bullets_and_checkboxes() {
+        use {Checkbox::*, ListBullet::*};
+        assert_eq!(
+            list_items("- [X] done\n3) [-] half\n  + [ ] nested\n10. plain\n"),
+            vec![
+                (0, Unordered('-'), Some(On), None),
+                (0, Ordered(3, ')'), Some(Partial), None),
+                (2, Unordered('+'), Some(Off), None),
+                (0, Ordered(10, '.'), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_item_description_term() {
+        use {Checkbox::*, ListBullet::*};
+        assert_eq!(
+            list_items("- /term/ :: what it means\n- [X] done :: \n- a::b\n"),
+            vec![
+                (0, Unordered('-'), None, Some("term".into())),
+                (0, Unordered('-'), Some(On), Some("done".into())),
+                (0, Unordered('-'), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn not_list_items() {
+        for input in [
+            "-no space\n",
+            "1.5 is a number\n",
+            "[X] no bullet\n",
+            "99999999999. is too big\n",
+        ] {
+            assert!(
+                matches!(&parse(input)[..], [AstNode::Block(_)]),
+                "{input:?} parsed as {:?}",
+                parse(input)
+            );
+        }
+    }
+
+    /// `Some(text)` if `bet` is nothing but plain characters
+    fn plain(bet: &BlockExprTree) -> Option<String> {
+        bet.iter()
+            .map(|ben| match ben.inner {
+                BlockExprNode::Char(c) => Some(c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn at(offset: usize, line: usize, column: usize) -> SourcePos {
+        SourcePos {
+            offset,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn stray_markers_stay_literal() {
+        for input in ["2 * 3 = 6", "and/or", "*not closed", "a / b * c", "**"] {
+            let bet = subparse_bet(input, SourcePos::default());
+            assert_eq!(plain(&bet).as_deref(), Some(input), "{bet:?}");
+        }
+    }
+
+    #[test]
+    fn emphasis_that_fails_to_subparse_falls_back() {
+        // The tab is a control character, which nothing inside emphasis accepts.
+        let bet = subparse_bet("*a\tb* c", SourcePos::default());
+        assert_eq!(plain(&bet).as_deref(), Some("*a\tb* c"));
+
+        // Only the inner marker falls back, the outer one still works.
+        let bet = subparse_bet("*x /y z*", SourcePos::default());
+        match &bet[..] {
+            [ben] => match &ben.inner {
+                BlockExprNode::Bold(inner) => assert_eq!(plain(inner).as_deref(), Some("x /y z")),
+                other => panic!("not bold: {other:?}"),
+            },
+            other => panic!("not a single node: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn nested_emphasis() {
+        let bet = subparse_bet("*a /b/ c*", SourcePos::default());
+        let inner = match &bet[..] {
+            [Spanned {
+                inner: BlockExprNode::Bold(inner),
+                ..
+            }] => inner,
+            other => panic!("not a single bold: {other:?}"),
+        };
+        assert!(matches!(inner[2].inner, BlockExprNode::Italic(_)));
+        assert_eq!(text(&bet), "a b c");
+    }
+
+    #[test]
+    fn emphasis_spans_are_in_the_outer_document() {
+        let bet = subparse_bet("x *bo*", at(10, 2, 3));
+        let bold = &bet[2];
+        assert_eq!(bold.span.start, at(12, 2, 5));
+        assert_eq!(bold.span.end, at(16, 2, 9));
+        let inner = match &bold.inner {
+            BlockExprNode::Bold(inner) => inner,
+            other => panic!("not bold: {other:?}"),
+        };
+        assert_eq!(inner[0].span.start, at(13, 2, 6));
+        assert_eq!(inner[1].span.end, at(15, 2, 8));
+
+        // Same through the whole file parser, on the second line.
+        let nodes = parse("first\n\nsee /here/\n");
+        let bet = match nodes.last() {
+            Some(AstNode::Block((_, bet))) => bet,
+            other => panic!("not a block: {other:?}"),
+        };
+        let italic = bet
+            .iter()
+            .find(|ben| matches!(ben.inner, BlockExprNode::Italic(_)))
+            .unwrap();
+        assert_eq!(italic.span.start, at(11, 3, 5));
+    }
+
+    #[test]
+    fn recovering_parse_resyncs_after_a_bad_block() {
+        let (nodes, diagnostics) = org_file_recovering("first\n\n\x01oops\n\nlast\n");
+        let blocks = nodes
+            .iter()
+            .filter_map(|node| match &node.inner {
+                AstNode::Block((_, bet)) if !is_linespace(bet) => Some(text(bet)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, vec!["first", "last"]);
+
+        let errors = nodes
+            .iter()
+            .filter(|node| matches!(node.inner, AstNode::Error { .. }))
+            .collect::<Vec<_>>();
+        match &errors[..] {
+            [Spanned {
+                inner: AstNode::Error { text, .. },
+                span,
+            }] => {
+                assert_eq!(text, "\x01oops\n");
+                assert_eq!(span.start, at(7, 3, 1));
+                assert_eq!(span.end, at(13, 4, 1));
+            }
+            other => panic!("not a single error: {other:?}"),
+        }
+
+        match &diagnostics[..] {
+            [diagnostic] => {
+                assert_eq!(diagnostic.span.start, at(7, 3, 1));
+                assert!(!diagnostic.expected.is_empty());
+            }
+            other => panic!("not a single diagnostic: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn recovering_parse_reports_every_bad_block() {
+        let (nodes, diagnostics) = org_file_recovering("\x01one\nok\n\x02two\n");
+        let errors = nodes
+            .iter()
+            .filter(|node| matches!(node.inner, AstNode::Error { .. }))
+            .count();
+        assert_eq!(errors, 2);
+        let lines = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.span.start.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![1, 3]);
+    }
+
+    #[test]
+    fn recovering_parse_of_a_good_file_matches_the_strict_one() {
+        let input = "* heading\ntext *bold*\n\n- item\n";
+        let (nodes, diagnostics) = org_file_recovering(input);
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            nodes.into_iter().map(|node| node.inner).collect::<Vec<_>>(),
+            parse(input)
+        );
+    }
+
+    /// The LaTeX of `input` if it is a single display math block
+    fn display_math_latex(input: &str) -> Option<String> {
+        match &parse(input)[..] {
+            [AstNode::Block((BlockType::Block, bet)), ..] if bet.len() == 1 => {
+                match &bet[0].inner {
+                    BlockExprNode::Math {
+                        display: true,
+                        latex,
+                    } => Some(latex.clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn display_math_on_a_single_line() {
+        for (input, latex) in [
+            ("$$x^2$$\n", "x^2"),
+            ("\\[x\\]\n", "x"),
+            (
+                "\\begin{equation}x\\end{equation}\n",
+                "\\begin{equation}x\\end{equation}",
+            ),
+        ] {
+            assert_eq!(
+                display_math_latex(input).as_deref(),
+                Some(latex),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn display_math_over_several_lines() {
+        assert_eq!(
+            display_math_latex("$$\na\n+ b\n$$\n").as_deref(),
+            Some("\na\n+ b\n")
+        );
+        // A blank line ends the paragraph, so it can't be inside math.
+        assert_eq!(display_math_latex("$$\na\n\nb\n$$\n"), None);
+        assert_eq!(display_math_latex("$$ $$\n"), None);
+    }
+
+    /// The footnote definitions and paragraphs of `input`, blank lines left out
+    fn footnote_blocks(input: &str) -> Vec<String> {
+        parse(input)
+            .iter()
+            .filter_map(|node| match node {
+                AstNode::FootnoteDefinition { label, definition } => {
+                    Some(format!("[fn:{label}] {}", text(definition)))
+                }
+                AstNode::Block((_, bet)) if !is_linespace(bet) => Some(text(bet)),
+                AstNode::Heading { title, .. } => Some(format!("* {}", text(title))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn footnote_definitions_go_on_over_lines() {
+        assert_eq!(
+            footnote_blocks("[fn:a] one\ntwo\n[fn:b] three\n\nafter\n"),
+            vec!["[fn:a] one\ntwo", "[fn:b] three", "after"]
+        );
+        assert_eq!(
+            footnote_blocks("[fn:a] one\n* next\n"),
+            vec!["[fn:a] one", "* next"]
+        );
+    }
 }
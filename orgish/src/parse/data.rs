@@ -3,6 +3,8 @@ use std::fmt::{Display, Pointer, Write};
 
 use serde::{Deserialize, Serialize};
 
+/// Also serialized by [`crate::json`]; changing the shape of these types means bumping
+/// [`crate::json::SCHEMA_VERSION`].
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum AstNode {
     SourceBlock {
@@ -16,12 +18,113 @@ pub enum AstNode {
         title: BlockExprTree,
         children: Vec<BackrefAstNode>,
         routing: Option<Route>,
+        /// The html id, unique within its page. Filled in by pass3.
+        anchor: Option<String>,
     },
     Block(BetBlock),
     /// Equivalent to html <hr>
     HorizRule,
-    ListItem(u16, Vec<BackrefAstNode>),
+    /// `#+BEGIN_QUOTE`, equivalent to html <blockquote>
+    Quote(Vec<BackrefAstNode>),
+    /// `#+BEGIN_CENTER`
+    Center(Vec<BackrefAstNode>),
+    /// `#+BEGIN_VERSE`, one BET per line. Line breaks and indentation are kept.
+    Verse(Vec<BlockExprTree>),
+    /// `#+BEGIN_EXAMPLE`, kept verbatim
+    Example(String),
+    /// A paragraph that is nothing but a link to an image, with a `#+CAPTION:` and/or
+    /// `#+ATTR_HTML:` above it. Equivalent to html <figure>.
+    Figure {
+        src: String,
+        caption: Option<BlockExprTree>,
+        /// From `#+ATTR_HTML: :alt A cookie :width 300`
+        attrs: Vec<(String, String)>,
+    },
+    /// `#+BEGIN_EXPORT backend` or `#+HTML:`, passed through as-is when rendering for `backend`
+    /// and dropped otherwise
+    Export {
+        /// Lowercase, e.g. `html`
+        backend: String,
+        content: String,
+    },
+    /// Only ever found inside an [`AstNode::List`]
+    ListItem {
+        indent: u16,
+        bullet: ListBullet,
+        checkbox: Option<Checkbox>,
+        /// The `term` in `- term :: description`
+        term: Option<BlockExprTree>,
+        children: Vec<BackrefAstNode>,
+    },
+    /// Equivalent to html <ul>, <ol> or <dl>. Consecutive list items are grouped into this in pass1.
+    List(ListKind, Vec<BackrefAstNode>),
     WarningBlock(BetBlock),
+    /// Equivalent to html <table>
+    Table {
+        header: Vec<TableRow>,
+        body: Vec<TableRow>,
+        /// Set by `<l>`/`<r>`/`<c>` cookies, indexed by column
+        alignments: Vec<Option<ColumnAlignment>>,
+    },
+    /// `[fn:label] definition` at the start of a line. Rendered in the page's [`AstNode::Footnotes`].
+    FootnoteDefinition {
+        label: String,
+        definition: BlockExprTree,
+    },
+    /// The footnotes of a page in order of first use, added to the end of every page by pass4
+    Footnotes(Vec<Footnote>),
+    /// Stands in for source the recovering parser had to skip, see [`crate::parse::parse_n_pass_recovering`]
+    Error {
+        /// The skipped source, verbatim
+        text: String,
+        message: String,
+    },
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Copy)]
+pub enum ListBullet {
+    /// `-` or `+`
+    Unordered(char),
+    /// `1.` or `1)`
+    Ordered(u32, char),
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Copy)]
+pub enum Checkbox {
+    /// `[ ]`
+    Off,
+    /// `[X]`
+    On,
+    /// `[-]`
+    Partial,
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Copy)]
+pub enum ListKind {
+    Unordered,
+    Ordered,
+    Description,
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Footnote {
+    /// 1-indexed, per page
+    pub number: usize,
+    /// `None` for anonymous `[fn:: text]` footnotes
+    pub label: Option<String>,
+    pub definition: BlockExprTree,
+    /// How many references point here, each one gets a back-link
+    pub references: usize,
+}
+
+/// The cells of a single table row
+pub type TableRow = Vec<BlockExprTree>;
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize, Copy)]
+pub enum ColumnAlignment {
+    Left,
+    Right,
+    Center,
 }
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +133,8 @@ pub struct BackrefAstNode {
     pub parent_idx: usize,
     pub inner: AstNode,
     pub render_group: Option<RenderGroup>,
+    /// Headings and list items span their children too
+    pub span: Span,
 }
 
 pub type PassedSyntaxTree = Vec<BackrefAstNode>;
@@ -57,7 +162,28 @@ pub enum BlockExprNode {
     FloatToggle(BlockExprTree),
     Warning(BlockExprTree),
     Code(String),
+    /// `@@backend:content@@`, like [`AstNode::Export`] but inline
+    Export {
+        /// Lowercase, e.g. `html`
+        backend: String,
+        content: String,
+    },
+    /// `$…$` and `\(…\)`, or with `display`, `\[…\]`, `$$…$$` and LaTeX environments like
+    /// `\begin{equation}…\end{equation}`. `latex` is what is between the delimiters, environments
+    /// are kept whole.
+    Math {
+        display: bool,
+        latex: String,
+    },
     Link(LinkTarget, Option<BlockExprTree>),
+    /// `[fn:label]`, `[fn:: inline definition]` or `[fn:label: inline definition]`
+    FootnoteRef {
+        label: Option<String>,
+        definition: Option<BlockExprTree>,
+        /// (footnote number, which reference to it this is), both 1-indexed. Filled in by pass4,
+        /// stays `None` for undefined footnotes.
+        number: Option<(usize, usize)>,
+    },
     /// One or more newlines
     Linespace,
     HeaderRouting(Route),
@@ -66,14 +192,53 @@ pub enum BlockExprNode {
 pub enum Directive {
     Id(String),
     Title(String),
-    /// Pre-pass datatype
-    Raw(String, String),
+    /// `#+DATE: 2022-05-01`, puts the page it is under into the feed
+    Date(String),
+    /// `#+REDIRECT: /new-page`, the page it is under has moved. pass1 percent-encodes the target,
+    /// so it can go into a `Location` header as is.
+    Redirect(String),
+    /// A `#+REDIRECT:` that pass1 refused, kept for the checks to report
+    BadRedirect {
+        target: String,
+        problem: String,
+    },
+    /// The content folder file a page came from, for pages with a file of their own. Not an Org
+    /// keyword, [`crate::parse::parse_n_pass_pages`] adds it.
+    SourceFile(String),
+    /// `#+ACCESS: group…`, see [`crate::access`]
+    Access(Vec<String>),
+    /// `#+OPTIONS: key:value…`, which apply to the page they are under
+    Options(Vec<(String, String)>),
+    /// `#+CAPTION:`, only kept when there is nothing to attach it to
+    Caption(BlockExprTree),
+    /// `#+ATTR_HTML: :key value…`, only kept when there is nothing to attach it to
+    AttrHtml(Vec<(String, String)>),
+    /// `#+TOC: headlines N`. pass3 fills in the headings of the page it is on, `depth` levels deep.
+    Toc {
+        depth: Option<u16>,
+        entries: Vec<TocEntry>,
+    },
+    /// Pre-pass datatype: the key, the value and where the value starts
+    Raw(String, String, SourcePos),
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct TocEntry {
+    /// Plain text, links can't be nested in the TOC's own links
+    pub title: String,
+    pub anchor: String,
+    pub children: Vec<TocEntry>,
 }
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum LinkTarget {
     Heading { title: String },
     External(String),
+    /// A link to a page in this document, resolved by pass3
+    Internal {
+        page: String,
+        anchor: Option<String>,
+    },
 }
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -128,10 +293,186 @@ impl BlockExprNode {
     pub fn is_char(&self) -> bool {
         matches!(self, Self::Char(..))
     }
+
+    /// The BET nested inside this node, if it has one.
+    pub fn children(&self) -> Option<&BlockExprTree> {
+        match self {
+            Self::Bold(bet)
+            | Self::Italic(bet)
+            | Self::Underline(bet)
+            | Self::Strikethrough(bet)
+            | Self::NonbreakingSpace(bet)
+            | Self::FloatToggle(bet)
+            | Self::Warning(bet)
+            | Self::Link(_, Some(bet))
+            | Self::FootnoteRef {
+                definition: Some(bet),
+                ..
+            } => Some(bet),
+            _ => None,
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Option<&mut BlockExprTree> {
+        match self {
+            Self::Bold(bet)
+            | Self::Italic(bet)
+            | Self::Underline(bet)
+            | Self::Strikethrough(bet)
+            | Self::NonbreakingSpace(bet)
+            | Self::FloatToggle(bet)
+            | Self::Warning(bet)
+            | Self::Link(_, Some(bet))
+            | Self::FootnoteRef {
+                definition: Some(bet),
+                ..
+            } => Some(bet),
+            _ => None,
+        }
+    }
 }
 
-pub type BlockExprTree = Vec<BlockExprNode>;
-pub type AbstractSyntaxTree = Vec<AstNode>;
+impl AstNode {
+    /// The child nodes of this node, if it has any.
+    pub fn children(&self) -> Option<&Vec<BackrefAstNode>> {
+        match self {
+            Self::Heading { children, .. }
+            | Self::ListItem { children, .. }
+            | Self::Quote(children)
+            | Self::Center(children) => Some(children),
+            Self::List(_, items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Option<&mut Vec<BackrefAstNode>> {
+        match self {
+            Self::Heading { children, .. }
+            | Self::ListItem { children, .. }
+            | Self::Quote(children)
+            | Self::Center(children) => Some(children),
+            Self::List(_, items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Every BET held directly by this node. Children are not included.
+    pub fn bets(&self) -> Vec<&BlockExprTree> {
+        match self {
+            Self::Heading { title, .. } => vec![title],
+            Self::Block((_, bet))
+            | Self::WarningBlock((_, bet))
+            | Self::FootnoteDefinition {
+                definition: bet, ..
+            } => vec![bet],
+            Self::ListItem { term, .. } => term.iter().collect(),
+            Self::Table { header, body, .. } => header.iter().chain(body).flatten().collect(),
+            Self::Verse(lines) => lines.iter().collect(),
+            Self::Figure { caption, .. } => caption.iter().collect(),
+            _ => vec![],
+        }
+    }
+
+    pub fn bets_mut(&mut self) -> Vec<&mut BlockExprTree> {
+        match self {
+            Self::Heading { title, .. } => vec![title],
+            Self::Block((_, bet))
+            | Self::WarningBlock((_, bet))
+            | Self::FootnoteDefinition {
+                definition: bet, ..
+            } => vec![bet],
+            Self::ListItem { term, .. } => term.iter_mut().collect(),
+            Self::Table { header, body, .. } => header.iter_mut().chain(body).flatten().collect(),
+            Self::Verse(lines) => lines.iter_mut().collect(),
+            Self::Figure { caption, .. } => caption.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+}
+
+pub type BlockExprTree = Vec<Spanned<BlockExprNode>>;
+pub type AbstractSyntaxTree = Vec<Spanned<AstNode>>;
+
+/// A position in the source file, as tracked by the parser.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SourcePos {
+    /// Byte offset
+    pub offset: usize,
+    /// 1-indexed
+    pub line: usize,
+    /// 1-indexed, counted in chars
+    pub column: usize,
+}
+
+impl Default for SourcePos {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Display for SourcePos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Where something came from in the source file. `end` is exclusive.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: SourcePos,
+    pub end: SourcePos,
+}
+
+impl Span {
+    /// From the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.start.fmt(f)
+    }
+}
+
+/// Something wrong with the source, found while parsing or by [`crate::check`].
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    /// What the parser would have accepted instead. Empty for everything but parse errors.
+    pub expected: Vec<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)?;
+        if !self.expected.is_empty() {
+            write!(f, " (expected {})", self.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+    pub inner: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(inner: T, span: Span) -> Self {
+        Self { inner, span }
+    }
+}
 
 impl Display for AstNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -146,31 +487,88 @@ impl Display for BlockExprNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             &BlockExprNode::Char(c) => f.write_char(c)?,
-            &BlockExprNode::Italic(ref bet) => {
-                for ben in bet {
-                    ben.fmt(f)?
+            BlockExprNode::Code(verbatim) => f.write_str(verbatim)?,
+            BlockExprNode::Math { latex, .. } => f.write_str(latex)?,
+            BlockExprNode::Link(LinkTarget::External(url), None) => f.write_str(url)?,
+            // Only the marker would show up in the text, never the definition.
+            BlockExprNode::FootnoteRef { .. } => {}
+            // Markup for some other output, not text
+            BlockExprNode::Export { .. } => {}
+            other => {
+                for ben in other.children().into_iter().flatten() {
+                    ben.inner.fmt(f)?
                 }
             }
-            _ => (),
         }
         Ok(())
     }
 }
 
-pub fn stringify_bet(bet: &Vec<BlockExprNode>) -> Result<String> {
+pub fn stringify_bet(bet: &BlockExprTree) -> Result<String> {
     let mut buf = String::new();
     for ben in bet {
-        write!(&mut buf, "{}", ben)?;
+        write!(&mut buf, "{}", ben.inner)?;
     }
     Ok(buf)
 }
 
+/// Is this BET just the [`BlockExprNode::Linespace`] between blocks?
+pub fn is_linespace(bet: &BlockExprTree) -> bool {
+    matches!(&bet[..], [ben] if ben.inner == BlockExprNode::Linespace)
+}
+
+/// Does this URL point at an image, going by its extension?
+pub fn is_image_url(url: &str) -> bool {
+    let path = url
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .unwrap_or_default();
+    match path.rsplit_once('.') {
+        Some((_, ext)) => ["png", "jpg", "jpeg", "gif", "svg", "webp", "avif"]
+            .contains(&ext.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// The value of `#+OPTIONS: key:value` directly under a page heading.
+pub fn page_option<'a>(page_children: &'a [BackrefAstNode], key: &str) -> Option<&'a str> {
+    page_children.iter().find_map(|node| match &node.inner {
+        AstNode::Directive(Directive::Options(options)) => options
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str()),
+        _ => None,
+    })
+}
+
+/// Where a page with `#+REDIRECT:` directly under its heading moved to.
+pub fn page_redirect(page_children: &[BackrefAstNode]) -> Option<&str> {
+    page_children.iter().find_map(|node| match &node.inner {
+        AstNode::Directive(Directive::Redirect(target)) => Some(target.as_str()),
+        _ => None,
+    })
+}
+
+/// The file a page came from, if it has one of its own. Pages in the main file don't.
+pub fn page_source_file(page_children: &[BackrefAstNode]) -> Option<&str> {
+    page_children.iter().find_map(|node| match &node.inner {
+        AstNode::Directive(Directive::SourceFile(file)) => Some(file.as_str()),
+        _ => None,
+    })
+}
+
+/// `#+OPTIONS: draft:t`: the page is served, but not advertised in feeds or sitemaps.
+pub fn is_draft(page_children: &[BackrefAstNode]) -> bool {
+    page_option(page_children, "draft") == Some("t")
+}
+
 impl BackrefAstNode {
-    pub fn new_unref(with: AstNode) -> Self {
+    pub fn new_unref(with: Spanned<AstNode>) -> Self {
         Self {
             parent_idx: 0,
-            inner: with,
+            inner: with.inner,
             render_group: None,
+            span: with.span,
         }
     }
 }
@@ -14,23 +14,160 @@ mod combiner;
 mod data;
 mod pass1;
 mod pass2;
+mod pass3;
+mod pass4;
 // Stuff doesn't break as weirdly anymore, and I'm tired of
 // fixing the +bazillion+, no, 11 tests.
 // #[cfg(test)]
 // mod test;
 
 pub use self::data::*;
+pub use self::pass3::{
+    has_scheme, refill_tocs, route_index, safe_url, slugify, toc_entries, PageAnchors, RouteIndex,
+    INDEX_PAGE,
+};
+pub use self::pass4::renumber_footnotes;
+
+fn run_passes(ast: AbstractSyntaxTree) -> Result<PassedSyntaxTree> {
+    pass4::pass4(pass3::pass3(pass2::pass2(pass1::flat_nodes_to_tree(
+        &mut ast.iter().peekable(),
+        vec![],
+    )?)?)?)
+}
+
+/// Parses as much of `input` as it can. Anything that fails to parse is skipped up to the end of
+/// the line and left in the tree as an [`AstNode::Error`], with a matching diagnostic.
+pub fn parse_n_pass_recovering(input: &str) -> Result<(PassedSyntaxTree, Vec<Diagnostic>)> {
+    let (ast, diagnostics) = combiner::org_file_recovering(input);
+    Ok((run_passes(ast)?, diagnostics))
+}
+
+/// A `.org` file of a content folder that is a page of its own, next to the main file.
+pub struct PageFile<'a> {
+    /// The file name in the content folder. Without `.org`, it is the route unless the file has an
+    /// `#+ID:`.
+    pub name: &'a str,
+    pub source: &'a str,
+}
+
+/// [`parse_n_pass_recovering`] for a content folder of several files. `main` is parsed as usual
+/// and each of `pages` becomes a top-level page after it, routed by its `#+ID:` (or its stem) and
+/// titled by its `#+TITLE:`, with its own headings one level further down and a
+/// [`Directive::SourceFile`] saying where it came from. Everything goes through the passes
+/// together, so links between the files resolve.
+///
+/// Diagnostics are per file, `main` first, and their spans point into that file.
+pub fn parse_n_pass_pages(main: &str, pages: &[PageFile]) -> Result<ParsedPages> {
+    let (mut ast, diagnostics) = combiner::org_file_recovering(main);
+    let mut all_diagnostics = vec![diagnostics];
+    for page in pages {
+        let (nodes, diagnostics) = combiner::org_file_recovering(page.source);
+        ast.extend(file_page(page, nodes));
+        all_diagnostics.push(diagnostics);
+    }
+    let ast = run_passes(ast)?;
+
+    // Each page file is a level 1 heading with everything else of the file under it.
+    let main_len = ast
+        .len()
+        .checked_sub(pages.len())
+        .filter(|&main_len| {
+            ast[main_len..]
+                .iter()
+                .all(|node| matches!(node.inner, AstNode::Heading { level: 1, .. }))
+        })
+        .ok_or_else(|| anyhow!("the page files didn't end up as one top-level page each"))?;
+    Ok(ParsedPages {
+        ast,
+        main_len,
+        diagnostics: all_diagnostics,
+    })
+}
+
+/// A content folder from [`parse_n_pass_pages`]
+pub struct ParsedPages {
+    pub ast: PassedSyntaxTree,
+    /// How many top-level nodes are from the main file. The page files come after them, one node
+    /// each and in order.
+    pub main_len: usize,
+    pub diagnostics: Vec<Vec<Diagnostic>>,
+}
+
+/// Puts a page heading in front of the flat nodes of a page file, and moves its headings under it.
+/// The heading is where the file's `#+TITLE:` (or `#+ID:`) is, so diagnostics about it point there.
+/// A route from the file name points at the `#+TITLE:` too, and both point at the empty span at
+/// the start of the file when there is neither keyword.
+fn file_page(page: &PageFile, mut nodes: AbstractSyntaxTree) -> AbstractSyntaxTree {
+    // Only the keywords above the first heading are about the whole file.
+    let keyword = |key: &str| {
+        nodes
+            .iter()
+            .take_while(|node| !matches!(node.inner, AstNode::Heading { .. }))
+            .find_map(|node| match &node.inner {
+                AstNode::Directive(Directive::Raw(k, v, value_pos))
+                    if k.eq_ignore_ascii_case(key) =>
+                {
+                    Some((v.trim().to_string(), node.span, *value_pos))
+                }
+                _ => None,
+            })
+    };
+    let title = keyword("title");
+    let start_of_file = Span {
+        start: SourcePos::default(),
+        end: SourcePos::default(),
+    };
+    let stem = page.name.strip_suffix(".org").unwrap_or(page.name);
+    let (route, route_span, _) = keyword("id").unwrap_or_else(|| {
+        let span = title.as_ref().map_or(start_of_file, |(_, span, _)| *span);
+        (stem.to_string(), span, SourcePos::default())
+    });
+    let (mut title, span) = match title {
+        Some((title, span, value_pos)) => (combiner::heading_title(&title, value_pos), span),
+        // A file name is no markup.
+        None => (
+            route
+                .chars()
+                .map(|c| Spanned::new(BlockExprNode::Char(c), route_span))
+                .collect(),
+            route_span,
+        ),
+    };
+    title.push(Spanned::new(
+        BlockExprNode::HeaderRouting(Route::Page(route)),
+        route_span,
+    ));
+
+    for node in &mut nodes {
+        if let AstNode::Heading { level, .. } = &mut node.inner {
+            *level += 1;
+        }
+    }
+
+    let heading = Spanned::new(
+        AstNode::Heading {
+            level: 1,
+            title,
+            children: vec![], // pass1 moves the file's nodes in here
+            routing: None,
+            anchor: None,
+        },
+        span,
+    );
+    let source_file = Spanned::new(
+        AstNode::Directive(Directive::SourceFile(page.name.to_string())),
+        span,
+    );
+    [heading, source_file].into_iter().chain(nodes).collect()
+}
 
 pub fn parse_n_pass(input: &str) -> Result<PassedSyntaxTree> {
     use combine::stream::position::Stream;
     use combine::EasyParser;
     use combiner::org_file;
 
-    match org_file().easy_parse(Stream::new(input)) {
-        Ok((ast, _)) => Ok(pass2::pass2(pass1::flat_nodes_to_tree(
-            &mut ast.iter().peekable(),
-            vec![],
-        )?)?),
+    match org_file().easy_parse(Stream::with_positioner(input, SourcePos::default())) {
+        Ok((ast, _)) => run_passes(ast),
         Err(pain) => {
             let pos = pain.position;
             let line_range: usize = 3;
@@ -47,19 +184,21 @@ pub fn parse_n_pass(input: &str) -> Result<PassedSyntaxTree> {
             let hl_syntax = ps.find_syntax_by_extension("org").unwrap();
             let mut highlighter = HighlightLines::new(hl_syntax, &ts.themes["base16-ocean.dark"]);
 
+            // The line before the error, unless the error is on the first line
+            let first_line = pos.line.saturating_sub(line_range / 2).max(1);
             let src: String = input
                 .lines()
-                .skip((pos.line as usize) - line_range + 1)
+                .skip(first_line - 1)
                 .take(line_range)
                 .enumerate()
                 .map(|(idx, line)| {
-                    let line_num = format!("{}|", idx + (pos.line as usize) - 1);
+                    let line_num = format!("{}|", idx + first_line);
                     let line_num_cy = line_num.cyan();
                     let line_with_ending = format!("{line_num_cy}    {line}\n");
                     let highlighted_line = highlighter.highlight(&line_with_ending, &ps);
                     let term_line = as_24_bit_terminal_escaped(&highlighted_line[..], false);
 
-                    if idx == line_range / 2 {
+                    if idx + first_line == pos.line {
                         let spaces = " ".repeat(pos.column as usize);
                         let arrows = format!(
                             "{s}{m}{s}",
@@ -90,3 +229,67 @@ pub fn parse_n_pass(input: &str) -> Result<PassedSyntaxTree> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_files(parsed: &ParsedPages) -> Vec<(String, Option<&str>)> {
+        parsed
+            .ast
+            .iter()
+            .filter_map(|node| match &node.inner {
+                AstNode::Heading {
+                    routing: Some(Route::Page(pg)),
+                    children,
+                    ..
+                } => Some((pg.clone(), page_source_file(children))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Where the page heading of each page file points
+    fn page_spans(main: &str, pages: &[PageFile]) -> Vec<Span> {
+        let parsed = parse_n_pass_pages(main, pages).unwrap();
+        parsed.ast[parsed.main_len..]
+            .iter()
+            .map(|node| node.span)
+            .collect()
+    }
+
+    #[test]
+    fn pages_without_id_point_at_the_title() {
+        let pages = [
+            PageFile {
+                name: "about.org",
+                source: "# comment\n#+TITLE: About me\n",
+            },
+            PageFile {
+                name: "untitled.org",
+                source: "just text\n",
+            },
+        ];
+        let spans = page_spans("* home :index:\n", &pages);
+        // Headings span their children as well, so only the start is the keyword's.
+        assert_eq!(spans[0].start.line, 2);
+        assert_eq!(spans[0].start.column, 1);
+        assert_eq!(spans[1].start, SourcePos::default());
+    }
+
+    #[test]
+    fn pages_know_their_file() {
+        let pages = [PageFile {
+            name: "about-me.org",
+            source: "#+ID: about\n#+TITLE: About me\n",
+        }];
+        let parsed = parse_n_pass_pages("* home :index:\n", &pages).unwrap();
+        assert_eq!(
+            page_files(&parsed),
+            vec![
+                ("index".to_string(), None),
+                ("about".to_string(), Some("about-me.org")),
+            ]
+        );
+    }
+}
@@ -3,8 +3,9 @@ use std::{iter::Peekable, slice::Iter};
 use anyhow::Result;
 
 use super::{
-    data::AstNode, AbstractSyntaxTree, BackrefAstNode, BlockExprNode, BlockExprTree, Directive,
-    Route,
+    combiner::subparse_bet, data::AstNode, is_image_url, is_linespace, safe_url,
+    AbstractSyntaxTree, BackrefAstNode, BlockExprNode, BlockExprTree, BlockType, Directive,
+    LinkTarget, ListBullet, ListKind, Route, Spanned, TableRow,
 };
 
 #[derive(PartialEq, Debug, Clone)]
@@ -21,27 +22,28 @@ pub enum StopReq {
 /// Instead, they are created in a separate pass
 #[tracing::instrument]
 pub fn flat_nodes_to_tree(
-    nodes: &mut Peekable<Iter<AstNode>>,
+    nodes: &mut Peekable<Iter<Spanned<AstNode>>>,
     stop_reqs: Vec<StopReq>,
 ) -> Result<AbstractSyntaxTree> {
     let mut out: AbstractSyntaxTree = vec![];
 
     while let Some(node) = {
+        let next = nodes.peek().map(|n| &n.inner);
         let fulfilled_reqs = stop_reqs.iter().filter(|req| match req {
-            StopReq::NextHeadingWithLevel(target_level) => match nodes.peek() {
+            StopReq::NextHeadingWithLevel(target_level) => match next {
                 Some(AstNode::Heading { level, .. }) if level <= target_level => true,
                 Some(_) | None => false,
             },
-            StopReq::NextListWithLevel(target_level) => match nodes.peek() {
-                Some(AstNode::ListItem(level, _)) if level <= target_level => true,
+            StopReq::NextListWithLevel(target_level) => match next {
+                Some(AstNode::ListItem { indent, .. }) if indent <= target_level => true,
                 Some(_) | None => false,
             },
-            StopReq::AnyHeading => match nodes.peek() {
+            StopReq::AnyHeading => match next {
                 Some(AstNode::Heading {..}) => true,
                 _ => false
             },
-            StopReq::Linespace => match nodes.peek() {
-                Some(AstNode::Block((_, bet))) if bet == &vec![BlockExprNode::Linespace] => true,
+            StopReq::Linespace => match next {
+                Some(AstNode::Block((_, bet))) if is_linespace(bet) => true,
                 _ => false
             },
         }).count();
@@ -52,24 +54,26 @@ pub fn flat_nodes_to_tree(
             nodes.next()
         }
     } {
-        match node {
+        let span = node.span;
+        match &node.inner {
             AstNode::Heading {
                 children: _,
                 level,
                 title,
                 routing: _,
+                anchor: _,
             } => {
                 // we have to do one more mini-pass to find this goddarn HeaderRouting thing
                 // because this is a bit more convenient for the user (:/path: can be at the end of the header title)
                 // TODO move into pass2 maybe?
-                let routing = title.iter().find_map::<Route, _>(|n| match n {
+                let routing = title.iter().find_map::<Route, _>(|n| match &n.inner {
                     BlockExprNode::HeaderRouting(route) => Some(route.clone()),
                     _ => None,
                 });
 
                 let mut title_bet = bet_pass(
                     &mut title.iter().peekable(),
-                    &mut BetPassState::new_with_ast_node(node.clone()),
+                    &mut BetPassState::new_with_ast_node(node.inner.clone()),
                 )?;
 
                 // XXX: Since we do not parse that nicely, we have
@@ -78,71 +82,344 @@ pub fn flat_nodes_to_tree(
                 // For example,
                 //  ** Blah blah :world:
                 // where BET is "Blah blah " instead of "Blah blah"
-                if let Some(BlockExprNode::Char(' ')) = title_bet.last() {
+                if let Some(BlockExprNode::Char(' ')) = title_bet.last().map(|n| &n.inner) {
                     title_bet.pop();
                 }
 
                 let mut new_stop_reqs = stop_reqs.clone();
                 new_stop_reqs.push(StopReq::NextHeadingWithLevel(*level));
-                out.push(AstNode::Heading {
-                    level: *level,
-                    title: title_bet,
-                    // Backreferences in children do not exist yet. We do that in another phase.
-                    children: flat_nodes_to_tree(nodes, new_stop_reqs)?
-                        .into_iter()
-                        .map(BackrefAstNode::new_unref)
-                        .collect::<Vec<_>>(),
-                    routing,
-                })
-            }
-
-            AstNode::ListItem(level, _) => {
+                // Backreferences in children do not exist yet. We do that in another phase.
+                let children = flat_nodes_to_tree(nodes, new_stop_reqs)?
+                    .into_iter()
+                    .map(BackrefAstNode::new_unref)
+                    .collect::<Vec<_>>();
+                out.push(Spanned::new(
+                    AstNode::Heading {
+                        level: *level,
+                        title: title_bet,
+                        routing,
+                        anchor: None, // pass3 does this
+                        children,
+                    },
+                    span,
+                ))
+            }
+
+            AstNode::ListItem {
+                indent,
+                bullet,
+                checkbox,
+                term,
+                children: _,
+            } => {
                 let mut new_stop_reqs = stop_reqs.clone();
-                new_stop_reqs.push(StopReq::NextListWithLevel(*level));
+                new_stop_reqs.push(StopReq::NextListWithLevel(*indent));
                 new_stop_reqs.push(StopReq::AnyHeading);
                 new_stop_reqs.push(StopReq::Linespace);
 
-                out.push(AstNode::ListItem(
-                    *level,
-                    // Backreferences in children do not exist yet. We do that in another phase.
-                    flat_nodes_to_tree(nodes, new_stop_reqs)?
-                        .into_iter()
-                        .map(BackrefAstNode::new_unref)
-                        .collect::<Vec<_>>(),
+                out.push(Spanned::new(
+                    AstNode::ListItem {
+                        indent: *indent,
+                        bullet: *bullet,
+                        checkbox: *checkbox,
+                        term: match term {
+                            Some(bet) => Some(bet_pass(
+                                &mut bet.iter().peekable(),
+                                &mut BetPassState::new_with_ast_node(node.inner.clone()),
+                            )?),
+                            None => None,
+                        },
+                        // Backreferences in children do not exist yet. We do that in another phase.
+                        children: flat_nodes_to_tree(nodes, new_stop_reqs)?
+                            .into_iter()
+                            .map(BackrefAstNode::new_unref)
+                            .collect::<Vec<_>>(),
+                    },
+                    span,
                 ))
             }
 
             // Optimization: Linespace is not very useful in the final AST,
             // but it is used in this pass for ListItem termination.
-            AstNode::Block((_, bet)) if bet == &vec![BlockExprNode::Linespace] => {}
+            AstNode::Block((_, bet)) if is_linespace(bet) => {}
+
+            AstNode::Block((ty, bet)) => out.push(Spanned::new(
+                AstNode::Block((
+                    ty.clone(),
+                    bet_pass(
+                        &mut bet.iter().peekable(),
+                        &mut BetPassState::new_with_ast_node(node.inner.clone()),
+                    )?,
+                )),
+                span,
+            )),
+
+            // The parser hands over the block's nodes flat, like a file of their own.
+            AstNode::Quote(children) | AstNode::Center(children) => {
+                let flat = children
+                    .iter()
+                    .map(|child| Spanned::new(child.inner.clone(), child.span))
+                    .collect::<Vec<_>>();
+                let children = flat_nodes_to_tree(&mut flat.iter().peekable(), vec![])?
+                    .into_iter()
+                    .map(BackrefAstNode::new_unref)
+                    .collect();
+                out.push(Spanned::new(
+                    match &node.inner {
+                        AstNode::Quote(_) => AstNode::Quote(children),
+                        _ => AstNode::Center(children),
+                    },
+                    span,
+                ))
+            }
+
+            AstNode::Verse(lines) => out.push(Spanned::new(
+                AstNode::Verse(
+                    lines
+                        .iter()
+                        .map(|line| {
+                            bet_pass(
+                                &mut line.iter().peekable(),
+                                &mut BetPassState::new_with_ast_node(node.inner.clone()),
+                            )
+                        })
+                        .collect::<Result<_>>()?,
+                ),
+                span,
+            )),
+
+            AstNode::FootnoteDefinition { label, definition } => out.push(Spanned::new(
+                AstNode::FootnoteDefinition {
+                    label: label.clone(),
+                    definition: bet_pass(
+                        &mut definition.iter().peekable(),
+                        &mut BetPassState::new_with_ast_node(node.inner.clone()),
+                    )?,
+                },
+                span,
+            )),
+
+            AstNode::Table {
+                header,
+                body,
+                alignments,
+            } => {
+                let rows_pass = |rows: &Vec<TableRow>| -> Result<Vec<TableRow>> {
+                    rows.iter()
+                        .map(|row| {
+                            row.iter()
+                                .map(|cell| {
+                                    bet_pass(
+                                        &mut cell.iter().peekable(),
+                                        &mut BetPassState::new_with_ast_node(node.inner.clone()),
+                                    )
+                                })
+                                .collect()
+                        })
+                        .collect()
+                };
+
+                out.push(Spanned::new(
+                    AstNode::Table {
+                        header: rows_pass(header)?,
+                        body: rows_pass(body)?,
+                        alignments: alignments.clone(),
+                    },
+                    span,
+                ))
+            }
 
-            AstNode::Block((ty, bet)) => out.push(AstNode::Block((
-                ty.clone(),
-                bet_pass(
-                    &mut bet.iter().peekable(),
-                    &mut BetPassState::new_with_ast_node(node.clone()),
-                )?,
-            ))),
+            AstNode::Directive(Directive::Raw(k, v, _)) if k.eq_ignore_ascii_case("html") => out
+                .push(Spanned::new(
+                    AstNode::Export {
+                        backend: "html".to_string(),
+                        content: format!("{v}\n"),
+                    },
+                    span,
+                )),
 
             AstNode::Directive(dir) => match dir {
-                Directive::Raw(k, v) => {
+                Directive::Raw(k, v, value_pos) => {
                     match match k.to_lowercase().as_str() {
                         "id" => Some(Directive::Id(v.to_string())),
                         "title" => Some(Directive::Title(v.to_string())),
+                        "caption" => Some(Directive::Caption(bet_pass(
+                            &mut subparse_bet(v, *value_pos).iter().peekable(),
+                            &mut BetPassState::new_with_ast_node(node.inner.clone()),
+                        )?)),
+                        "attr_html" => Some(Directive::AttrHtml(attr_pairs(v))),
+                        "date" => Some(Directive::Date(v.to_string())),
+                        "redirect" => Some(match redirect_target(v) {
+                            Ok(target) => Directive::Redirect(target),
+                            Err(problem) => Directive::BadRedirect {
+                                target: v.trim().to_string(),
+                                problem,
+                            },
+                        }),
+                        "access" => Some(Directive::Access(
+                            v.split_whitespace().map(str::to_string).collect(),
+                        )),
+                        "options" => Some(Directive::Options(
+                            v.split_whitespace()
+                                .filter_map(|opt| opt.split_once(':'))
+                                .map(|(k, v)| (k.to_string(), v.to_string()))
+                                .collect(),
+                        )),
+                        // Upstream also has TOCs of tables and listings, we only do headlines.
+                        "toc" => match v.split_whitespace().collect::<Vec<_>>()[..] {
+                            ["headlines"] => Some(Directive::Toc {
+                                depth: None,
+                                entries: vec![],
+                            }),
+                            ["headlines", depth, ..] => Some(Directive::Toc {
+                                depth: depth.parse().ok(),
+                                entries: vec![],
+                            }),
+                            _ => None,
+                        },
                         _ => None,
                     } {
                         None => {}
-                        Some(dir) => out.push(AstNode::Directive(dir)),
+                        Some(dir) => out.push(Spanned::new(AstNode::Directive(dir), span)),
                     };
                 }
-                _ => unreachable!(),
+                // Made by the parser itself, like `Directive::SourceFile`, so nothing to do.
+                dir => out.push(Spanned::new(AstNode::Directive(dir.clone()), span)),
             },
 
-            other => out.push(other.clone()),
+            _ => out.push(node.clone()),
         }
     }
 
-    Ok(out)
+    // Headings and list items stretch over their children. Blocks already span theirs.
+    for node in &mut out {
+        if let Some(last) = node.inner.children().and_then(|c| c.last()) {
+            if last.span.end > node.span.end {
+                node.span = node.span.to(last.span);
+            }
+        }
+    }
+
+    Ok(group_list_items(attach_figures(out)))
+}
+
+/// `:alt A cookie :width 300` -> `[("alt", "A cookie"), ("width", "300")]`
+fn attr_pairs(value: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = vec![];
+    for word in value.split_whitespace() {
+        match (word.strip_prefix(':'), pairs.last_mut()) {
+            (Some(key), _) => pairs.push((key.to_lowercase(), String::new())),
+            (None, Some((_, v))) if v.is_empty() => v.push_str(word),
+            (None, Some((_, v))) => {
+                v.push(' ');
+                v.push_str(word)
+            }
+            // Words before the first key don't belong anywhere.
+            (None, None) => {}
+        }
+    }
+    pairs
+}
+
+/// The URL of a paragraph that is nothing but a link to an image.
+fn standalone_image(bet: &BlockExprTree) -> Option<&str> {
+    let mut bens = bet
+        .iter()
+        .filter(|ben| !matches!(ben.inner, BlockExprNode::Char(c) if c.is_whitespace()));
+    match (bens.next().map(|ben| &ben.inner), bens.next()) {
+        (Some(BlockExprNode::Link(LinkTarget::External(url), None)), None) if is_image_url(url) => {
+            Some(url)
+        }
+        _ => None,
+    }
+}
+
+/// Turn `#+CAPTION:` and `#+ATTR_HTML:` followed by an image paragraph into an [`AstNode::Figure`].
+/// The keywords stay where they are if anything else follows them.
+fn attach_figures(nodes: AbstractSyntaxTree) -> AbstractSyntaxTree {
+    let mut out: AbstractSyntaxTree = vec![];
+    // Where the keywords right before the current node start in `out`
+    let mut keywords_start = 0;
+    for node in nodes {
+        let src = match &node.inner {
+            AstNode::Directive(Directive::Caption(_) | Directive::AttrHtml(_)) => {
+                out.push(node);
+                continue;
+            }
+            AstNode::Block((BlockType::Block, bet)) if keywords_start < out.len() => {
+                standalone_image(bet).map(str::to_string)
+            }
+            _ => None,
+        };
+
+        match src {
+            Some(src) => {
+                let mut caption = None;
+                let mut attrs = vec![];
+                let span = out[keywords_start].span.to(node.span);
+                for keyword in out.drain(keywords_start..) {
+                    match keyword.inner {
+                        AstNode::Directive(Directive::Caption(bet)) => caption = Some(bet),
+                        AstNode::Directive(Directive::AttrHtml(pairs)) => attrs.extend(pairs),
+                        _ => unreachable!(),
+                    }
+                }
+                out.push(Spanned::new(
+                    AstNode::Figure {
+                        src,
+                        caption,
+                        attrs,
+                    },
+                    span,
+                ));
+            }
+            None => out.push(node),
+        }
+        keywords_start = out.len();
+    }
+    out
+}
+
+/// Wrap runs of sibling [`AstNode::ListItem`]s into [`AstNode::List`]s.
+///
+/// Deeper items are already children of the item above them by now,
+/// so this only has to look at one level at a time.
+fn group_list_items(nodes: AbstractSyntaxTree) -> AbstractSyntaxTree {
+    let mut out: AbstractSyntaxTree = vec![];
+    for node in nodes {
+        let kind = match &node.inner {
+            AstNode::ListItem { term: Some(_), .. } => ListKind::Description,
+            AstNode::ListItem {
+                bullet: ListBullet::Ordered(..),
+                ..
+            } => ListKind::Ordered,
+            AstNode::ListItem { .. } => ListKind::Unordered,
+            _ => {
+                out.push(node);
+                continue;
+            }
+        };
+
+        match out.last_mut() {
+            // A different kind of bullet starts a new list.
+            Some(Spanned {
+                inner: AstNode::List(last_kind, items),
+                span,
+            }) if *last_kind == kind => {
+                *span = span.to(node.span);
+                items.push(BackrefAstNode::new_unref(node))
+            }
+            _ => {
+                let span = node.span;
+                out.push(Spanned::new(
+                    AstNode::List(kind, vec![BackrefAstNode::new_unref(node)]),
+                    span,
+                ))
+            }
+        }
+    }
+
+    out
 }
 
 #[derive(Debug)]
@@ -168,19 +445,20 @@ impl BetPassState {
 
 #[tracing::instrument]
 fn bet_pass(
-    nodes: &mut Peekable<Iter<BlockExprNode>>,
+    nodes: &mut Peekable<Iter<Spanned<BlockExprNode>>>,
     state: &mut BetPassState,
 ) -> Result<BlockExprTree> {
     let mut out: BlockExprTree = vec![];
     for node in nodes {
         // debug!("bet_pass: {:?}", &node);
-        match node {
+        let span = node.span;
+        match &node.inner {
             BlockExprNode::NonbreakingSpace(bet) => out.append(&mut bet_pass(
                 &mut bet.iter().peekable(),
                 state.inside_nbsp(),
             )?),
             BlockExprNode::Char(' ') if state.inside_nbsp => {
-                out.push(BlockExprNode::Char('\u{a0}'))
+                out.push(Spanned::new(BlockExprNode::Char('\u{a0}'), span))
             }
 
             // hey hey, wouldn't it be neat if links like "Org Mode" wouldn't wrap around?
@@ -194,34 +472,191 @@ fn bet_pass(
             //         debug!("BET in BEN::Link {:#?}", bet);
             //         out.push(BlockExprNode::Link(link.to_string(), bet_pass(&mut bet.iter().peekable(), state.inside_nbsp())))
             //     },
-            BlockExprNode::Bold(bet) => out.push(BlockExprNode::Bold(bet_pass(
-                &mut bet.iter().peekable(),
-                state,
-            )?)),
-            BlockExprNode::Italic(bet) => out.push(BlockExprNode::Italic(bet_pass(
-                &mut bet.iter().peekable(),
-                state,
-            )?)),
-            BlockExprNode::Underline(bet) => out.push(BlockExprNode::Underline(bet_pass(
-                &mut bet.iter().peekable(),
-                state,
-            )?)),
-            BlockExprNode::Strikethrough(bet) => out.push(BlockExprNode::Strikethrough(bet_pass(
-                &mut bet.iter().peekable(),
-                state,
-            )?)),
-            BlockExprNode::Link(url, maybe_bet) => out.push(BlockExprNode::Link(
-                url.clone(),
-                match maybe_bet.as_ref() {
-                    Some(bet) => Some(bet_pass(&mut bet.iter().peekable(), state)?),
-                    None => None,
+            BlockExprNode::Bold(bet) => out.push(Spanned::new(
+                BlockExprNode::Bold(bet_pass(&mut bet.iter().peekable(), state)?),
+                span,
+            )),
+            BlockExprNode::Italic(bet) => out.push(Spanned::new(
+                BlockExprNode::Italic(bet_pass(&mut bet.iter().peekable(), state)?),
+                span,
+            )),
+            BlockExprNode::Underline(bet) => out.push(Spanned::new(
+                BlockExprNode::Underline(bet_pass(&mut bet.iter().peekable(), state)?),
+                span,
+            )),
+            BlockExprNode::Strikethrough(bet) => out.push(Spanned::new(
+                BlockExprNode::Strikethrough(bet_pass(&mut bet.iter().peekable(), state)?),
+                span,
+            )),
+            BlockExprNode::Link(url, maybe_bet) => out.push(Spanned::new(
+                BlockExprNode::Link(
+                    url.clone(),
+                    match maybe_bet.as_ref() {
+                        Some(bet) => Some(bet_pass(&mut bet.iter().peekable(), state)?),
+                        None => None,
+                    },
+                ),
+                span,
+            )),
+            BlockExprNode::FootnoteRef {
+                label,
+                definition,
+                number,
+            } => out.push(Spanned::new(
+                BlockExprNode::FootnoteRef {
+                    label: label.clone(),
+                    definition: match definition.as_ref() {
+                        Some(bet) => Some(bet_pass(&mut bet.iter().peekable(), state)?),
+                        None => None,
+                    },
+                    number: *number,
                 },
+                span,
             )),
             // we discard HeaderRoutings since they should have already been looked ahead for
             BlockExprNode::HeaderRouting(..) => {}
-            other => out.push(other.clone()),
+            _ => out.push(node.clone()),
         }
     }
 
     Ok(out)
 }
+
+/// The target of a `#+REDIRECT:`, with everything but printable ASCII percent-encoded so it is a
+/// valid `Location` header. Targets that would run code are refused.
+fn redirect_target(value: &str) -> Result<String, String> {
+    let target = value.trim();
+    if target.is_empty() {
+        return Err("#+REDIRECT: without a target".to_string());
+    }
+    if safe_url(target) != target {
+        return Err(format!("redirect target {target:?} would run code"));
+    }
+
+    let mut encoded = String::with_capacity(target.len());
+    for c in target.chars() {
+        if c.is_ascii_graphic() {
+            encoded.push(c);
+        } else {
+            for byte in c.to_string().bytes() {
+                encoded.push_str(&format!("%{byte:02X}"));
+            }
+        }
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_n_pass, stringify_bet, SourcePos};
+
+    /// `Unordered[(a Ordered[(b)]) (c)]` for a list with a nested list in its first item
+    fn shape(nodes: &[BackrefAstNode]) -> String {
+        nodes
+            .iter()
+            .map(|node| match &node.inner {
+                AstNode::List(kind, items) => format!("{kind:?}[{}]", shape(items)),
+                AstNode::ListItem { children, .. } => format!("({})", shape(children)),
+                AstNode::Block((_, bet)) => stringify_bet(bet).unwrap(),
+                other => format!("{other:?}"),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn list_shape(input: &str) -> String {
+        shape(&parse_n_pass(input).unwrap())
+    }
+
+    #[test]
+    fn sibling_items_are_one_list() {
+        assert_eq!(list_shape("- a\n- b\n- c\n"), "Unordered[(a) (b) (c)]");
+        assert_eq!(list_shape("1. a\n2) b\n"), "Ordered[(a) (b)]");
+        assert_eq!(list_shape("- x :: a\n- y :: b\n"), "Description[(a) (b)]");
+    }
+
+    #[test]
+    fn another_kind_of_item_starts_another_list() {
+        assert_eq!(
+            list_shape("- a\n1. b\n- c :: d\n"),
+            "Unordered[(a)] Ordered[(b)] Description[(d)]"
+        );
+    }
+
+    #[test]
+    fn deeper_items_nest() {
+        assert_eq!(
+            list_shape("- a\n  1. b\n  2. c\n- d\n"),
+            "Unordered[(a Ordered[(b) (c)]) (d)]"
+        );
+        assert_eq!(
+            list_shape("- a\n  - b\n    - c\n- d\n"),
+            "Unordered[(a Unordered[(b Unordered[(c)])]) (d)]"
+        );
+    }
+
+    #[test]
+    fn shallower_text_ends_the_list() {
+        assert_eq!(
+            list_shape("- a\n  - b\n\nafter\n"),
+            "Unordered[(a Unordered[(b)])] after"
+        );
+    }
+
+    #[test]
+    fn captions_point_into_their_value() {
+        // Trailing whitespace and chars wider than a byte don't move the value.
+        let ast = parse_n_pass("#+CAPTION:  é *b*  \n").unwrap();
+        let caption = match &ast[0].inner {
+            AstNode::Directive(Directive::Caption(caption)) => caption,
+            other => panic!("not a caption: {other:?}"),
+        };
+        let bold = caption
+            .iter()
+            .find(|node| matches!(node.inner, BlockExprNode::Bold(_)))
+            .unwrap();
+        assert_eq!(
+            bold.span.start,
+            SourcePos {
+                offset: 15,
+                line: 1,
+                column: 15
+            }
+        );
+    }
+
+    fn redirects(input: &str) -> Vec<Directive> {
+        let mut found = vec![];
+        for node in &parse_n_pass(input).unwrap()[0].inner.children().unwrap()[..] {
+            if let AstNode::Directive(
+                dir @ (Directive::Redirect(_) | Directive::BadRedirect { .. }),
+            ) = &node.inner
+            {
+                found.push(dir.clone());
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn redirect_targets_are_encoded() {
+        assert_eq!(
+            redirects("* old :old:\n#+REDIRECT: /über uns?q=a b \n"),
+            vec![Directive::Redirect("/%C3%BCber%20uns?q=a%20b".to_string())]
+        );
+    }
+
+    #[test]
+    fn code_is_no_redirect_target() {
+        for target in ["javascript:alert(1)", "JavaScript:alert(1)", "data:,hi"] {
+            assert!(
+                matches!(
+                    &redirects(&format!("* old :old:\n#+REDIRECT: {target}\n"))[..],
+                    [Directive::BadRedirect { .. }]
+                ),
+                "{target:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{
+    stringify_bet, AstNode, BackrefAstNode, BlockExprNode, BlockExprTree, Directive, LinkTarget,
+    PassedSyntaxTree, Route, TocEntry,
+};
+
+/// The page the site root shows, so `[[/]]` links to it.
+pub const INDEX_PAGE: &str = "index";
+
+/// Where every heading lives, keyed by the `Route::Page` it is under.
+#[derive(Debug, Default)]
+pub struct RouteIndex {
+    pub pages: BTreeMap<String, PageAnchors>,
+}
+
+#[derive(Debug, Default)]
+pub struct PageAnchors {
+    pub anchors: HashSet<String>,
+    /// Plain text heading title -> anchor. The first heading with a title wins.
+    pub titles: HashMap<String, String>,
+}
+
+impl RouteIndex {
+    /// Find what an internal-looking link points at. Links to other
+    /// sites and links that do not resolve give `None`.
+    ///
+    /// `page` is the page the link is on, for `#section` and `[[*Heading]]` links.
+    pub fn resolve(&self, target: &LinkTarget, page: Option<&str>) -> Option<LinkTarget> {
+        match target {
+            LinkTarget::Heading { title } => {
+                // Prefer the page we are on, then look everywhere else.
+                let here = page.and_then(|pg| Some((pg, self.pages.get(pg)?)));
+                here.into_iter()
+                    .chain(
+                        self.pages
+                            .iter()
+                            .map(|(pg, anchors)| (pg.as_str(), anchors)),
+                    )
+                    .find_map(|(pg, anchors)| {
+                        Some(LinkTarget::Internal {
+                            page: pg.to_string(),
+                            anchor: Some(anchors.titles.get(title)?.clone()),
+                        })
+                    })
+            }
+            LinkTarget::External(url) if !has_scheme(url) => {
+                let (path, fragment) = match url.split_once('#') {
+                    Some((path, fragment)) => (path, Some(fragment)),
+                    None => (&url[..], None),
+                };
+                let pg = match path {
+                    // `#section` is on the page the link is on
+                    "" => page?,
+                    "/" => INDEX_PAGE,
+                    path => path.trim_start_matches('/'),
+                };
+                let anchors = self.pages.get(pg)?;
+                match fragment {
+                    Some(f) if !anchors.anchors.contains(f) => None,
+                    _ => Some(LinkTarget::Internal {
+                        page: pg.to_string(),
+                        anchor: fragment.map(str::to_string),
+                    }),
+                }
+            }
+            LinkTarget::External(_) | LinkTarget::Internal { .. } => None,
+        }
+    }
+}
+
+/// `https:`, `mailto:` and friends
+pub fn has_scheme(url: &str) -> bool {
+    match url.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// Replace URLs that would run code when clicked with a harmless `#`.
+/// The result still needs to be escaped.
+pub fn safe_url(url: &str) -> String {
+    // Browsers ignore whitespace and control characters in the scheme, so "java\tscript:" works.
+    let scheme = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .take_while(|c| *c != ':')
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let has_scheme = url.contains(':') && !scheme.contains(|c| "/?#".contains(c));
+
+    match &scheme[..] {
+        "javascript" | "vbscript" | "data" if has_scheme => "#".to_string(),
+        _ => url.to_string(),
+    }
+}
+
+/// Turn a heading title into something that looks nice after a `#`.
+pub fn slugify(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .intersperse("-")
+        .collect::<String>();
+
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug
+    }
+}
+
+fn plain_title(title: &BlockExprTree) -> Result<String> {
+    Ok(stringify_bet(title)?
+        .split_whitespace()
+        .intersperse(" ")
+        .collect())
+}
+
+/// Explicit `:#section:` names go first, so slugs can never steal them.
+fn collect_sections(nodes: &[BackrefAstNode], page: Option<&str>, index: &mut RouteIndex) {
+    for node in nodes {
+        let mut page = page;
+        if let AstNode::Heading { routing, .. } = &node.inner {
+            match routing {
+                Some(Route::Page(pg)) => {
+                    index.pages.entry(pg.clone()).or_default();
+                    page = Some(pg);
+                }
+                Some(Route::Section(section)) => {
+                    if let Some(pg) = page {
+                        index
+                            .pages
+                            .entry(pg.to_string())
+                            .or_default()
+                            .anchors
+                            .insert(section.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(children) = node.inner.children() {
+            collect_sections(children, page, index);
+        }
+    }
+}
+
+fn assign_anchors(
+    nodes: &mut [BackrefAstNode],
+    page: Option<&str>,
+    index: &mut RouteIndex,
+) -> Result<()> {
+    for node in nodes {
+        let mut page = page.map(str::to_string);
+        if let AstNode::Heading {
+            title,
+            routing,
+            anchor,
+            ..
+        } = &mut node.inner
+        {
+            if let Some(Route::Page(pg)) = routing {
+                page = Some(pg.clone());
+            }
+
+            let plain = plain_title(title)?;
+            let mut scratch = PageAnchors::default();
+            let anchors = match &page {
+                Some(pg) => index.pages.entry(pg.clone()).or_default(),
+                // Headings outside of any page can't be linked to, but still get an id.
+                None => &mut scratch,
+            };
+
+            let new_anchor = match routing {
+                Some(Route::Section(section)) => section.clone(),
+                _ => {
+                    let slug = slugify(&plain);
+                    let mut candidate = slug.clone();
+                    let mut n = 1;
+                    while anchors.anchors.contains(&candidate) {
+                        n += 1;
+                        candidate = format!("{slug}-{n}");
+                    }
+                    anchors.anchors.insert(candidate.clone());
+                    candidate
+                }
+            };
+            anchors
+                .titles
+                .entry(plain)
+                .or_insert_with(|| new_anchor.clone());
+            *anchor = Some(new_anchor);
+        }
+        if let Some(children) = node.inner.children_mut() {
+            assign_anchors(children, page.as_deref(), index)?;
+        }
+    }
+    Ok(())
+}
+
+fn resolve_bet(bet: &mut BlockExprTree, page: Option<&str>, index: &RouteIndex) {
+    for ben in bet {
+        if let BlockExprNode::Link(target, _) = &mut ben.inner {
+            if let Some(resolved) = index.resolve(target, page) {
+                *target = resolved;
+            }
+        }
+        if let Some(children) = ben.inner.children_mut() {
+            resolve_bet(children, page, index);
+        }
+    }
+}
+
+fn resolve_links(nodes: &mut [BackrefAstNode], page: Option<&str>, index: &RouteIndex) {
+    for node in nodes {
+        let page = match &node.inner {
+            AstNode::Heading {
+                routing: Some(Route::Page(pg)),
+                ..
+            } => Some(pg.clone()),
+            _ => page.map(str::to_string),
+        };
+        for bet in node.inner.bets_mut() {
+            resolve_bet(bet, page.as_deref(), index);
+        }
+        if let Some(children) = node.inner.children_mut() {
+            resolve_links(children, page.as_deref(), index);
+        }
+    }
+}
+
+/// The headings under a page, `depth` levels deep. Pages below it and render groups are left out.
+pub fn toc_entries(page_children: &[BackrefAstNode], depth: Option<u16>) -> Vec<TocEntry> {
+    if depth == Some(0) {
+        return vec![];
+    }
+    page_children
+        .iter()
+        .filter_map(|node| match &node.inner {
+            AstNode::Heading {
+                routing: Some(Route::Page(_) | Route::RenderGroup(_)),
+                ..
+            } => None,
+            AstNode::Heading {
+                title,
+                children,
+                anchor: Some(anchor),
+                ..
+            } => Some(TocEntry {
+                title: plain_title(title).ok()?,
+                anchor: anchor.clone(),
+                children: toc_entries(children, depth.map(|d| d - 1)),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fill in the `#+TOC:` entries of a tree that already went through [`pass3`] again, e.g. after
+/// [`crate::access::strip_restricted`] took headings out that the tables of contents still list.
+pub fn refill_tocs(nodes: &mut [BackrefAstNode]) {
+    fill_tocs(nodes, None);
+}
+
+fn fill_tocs(nodes: &mut [BackrefAstNode], page_children: Option<&[BackrefAstNode]>) {
+    for node in nodes {
+        if let AstNode::Heading {
+            routing: Some(Route::Page(_)),
+            children,
+            ..
+        } = &mut node.inner
+        {
+            let page_children = children.clone();
+            fill_tocs(children, Some(&page_children));
+            continue;
+        }
+
+        if let (AstNode::Directive(Directive::Toc { depth, entries }), Some(page_children)) =
+            (&mut node.inner, page_children)
+        {
+            *entries = toc_entries(page_children, *depth);
+        }
+        if let Some(children) = node.inner.children_mut() {
+            fill_tocs(children, page_children);
+        }
+    }
+}
+
+/// Rebuild the [`RouteIndex`] of a tree that already went through [`pass3`].
+pub fn route_index(nodes: &[BackrefAstNode]) -> RouteIndex {
+    let mut index = RouteIndex::default();
+    index_anchors(nodes, None, &mut index);
+    index
+}
+
+fn index_anchors(nodes: &[BackrefAstNode], page: Option<&str>, index: &mut RouteIndex) {
+    for node in nodes {
+        let mut page = page;
+        if let AstNode::Heading {
+            title,
+            routing,
+            anchor,
+            ..
+        } = &node.inner
+        {
+            if let Some(Route::Page(pg)) = routing {
+                page = Some(pg);
+            }
+            if let (Some(pg), Some(anchor)) = (page, anchor) {
+                let anchors = index.pages.entry(pg.to_string()).or_default();
+                anchors.anchors.insert(anchor.clone());
+                if let Ok(plain) = plain_title(title) {
+                    anchors
+                        .titles
+                        .entry(plain)
+                        .or_insert_with(|| anchor.clone());
+                }
+            }
+        }
+        if let Some(children) = node.inner.children() {
+            index_anchors(children, page, index);
+        }
+    }
+}
+
+/// Resolution pass: give every heading an anchor, point internal links at them and fill in
+/// the tables of contents.
+#[tracing::instrument(skip_all)]
+pub fn pass3(mut nodes: PassedSyntaxTree) -> Result<PassedSyntaxTree> {
+    let mut index = RouteIndex::default();
+    collect_sections(&nodes, None, &mut index);
+    assign_anchors(&mut nodes, None, &mut index)?;
+    resolve_links(&mut nodes, None, &index);
+    fill_tocs(&mut nodes, None);
+
+    Ok(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_n_pass;
+
+    fn anchors(nodes: &[BackrefAstNode], out: &mut Vec<String>) {
+        for node in nodes {
+            if let AstNode::Heading {
+                anchor: Some(anchor),
+                ..
+            } = &node.inner
+            {
+                out.push(anchor.clone());
+            }
+            if let Some(children) = node.inner.children() {
+                anchors(children, out);
+            }
+        }
+    }
+
+    fn links(nodes: &[BackrefAstNode], out: &mut Vec<LinkTarget>) {
+        fn bet_links(bet: &BlockExprTree, out: &mut Vec<LinkTarget>) {
+            for ben in bet {
+                if let BlockExprNode::Link(target, _) = &ben.inner {
+                    out.push(target.clone());
+                }
+                if let Some(children) = ben.inner.children() {
+                    bet_links(children, out);
+                }
+            }
+        }
+        for node in nodes {
+            for bet in node.inner.bets() {
+                bet_links(bet, out);
+            }
+            if let Some(children) = node.inner.children() {
+                links(children, out);
+            }
+        }
+    }
+
+    fn parse(input: &str) -> (Vec<String>, Vec<LinkTarget>) {
+        let tree = parse_n_pass(input).unwrap();
+        let (mut a, mut l) = (vec![], vec![]);
+        anchors(&tree, &mut a);
+        links(&tree, &mut l);
+        (a, l)
+    }
+
+    fn internal(page: &str, anchor: Option<&str>) -> LinkTarget {
+        LinkTarget::Internal {
+            page: page.to_string(),
+            anchor: anchor.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn slugs_and_section_names() {
+        let (anchors, _) = parse(
+            "* Page :page:\n** Hello, World!\n** Hello world\n** Named :#hello-world-2:\n** ???\n",
+        );
+        // The explicit name is taken first, so the second slug has to skip it.
+        let expected = [
+            "page",
+            "hello-world",
+            "hello-world-3",
+            "hello-world-2",
+            "section",
+        ];
+        assert_eq!(anchors, expected);
+    }
+
+    #[test]
+    fn anchors_are_per_page() {
+        let (anchors, _) = parse("* One :one:\n** Intro\n* Two :two:\n** Intro\n");
+        assert_eq!(anchors, vec!["one", "intro", "two", "intro"]);
+    }
+
+    #[test]
+    fn heading_links_prefer_the_page_they_are_on() {
+        let (_, links) = parse(
+            "* One :one:\n** Intro\n[[*Intro]] [[*Only here]]\n* Two :two:\n** Intro\n[[*Intro]]\n** Only here\n",
+        );
+        assert_eq!(
+            links,
+            vec![
+                internal("one", Some("intro")),
+                internal("two", Some("only-here")),
+                internal("two", Some("intro")),
+            ]
+        );
+    }
+
+    #[test]
+    fn path_links_resolve_to_known_anchors() {
+        let (_, links) = parse(
+            "* One :one:\n[[/two#sec]] [[/two]] [[#local]]\n** Local :#local:\n* Two :two:\n** Sec :#sec:\n",
+        );
+        assert_eq!(
+            links,
+            vec![
+                internal("two", Some("sec")),
+                internal("two", None),
+                internal("one", Some("local")),
+            ]
+        );
+    }
+
+    #[test]
+    fn root_links_go_to_the_index() {
+        let (_, links) = parse(
+            "* Home :index:\n** Top :#top:\n* Other :other:\n[[/][home]] [[/#top]] [[#top]]\n",
+        );
+        assert_eq!(
+            links,
+            vec![
+                internal("index", None),
+                internal("index", Some("top")),
+                LinkTarget::External("#top".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unresolved_links_are_left_alone() {
+        let (_, links) = parse(
+            "* One :one:\n[[*Nowhere]] [[/two#missing]] [[/nope]] [[https://example.com/one]]\n* Two :two:\n",
+        );
+        assert_eq!(
+            links,
+            vec![
+                LinkTarget::Heading {
+                    title: "Nowhere".to_string()
+                },
+                LinkTarget::External("/two#missing".to_string()),
+                LinkTarget::External("/nope".to_string()),
+                LinkTarget::External("https://example.com/one".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::Result;
+use std::collections::HashMap;
+
+use super::{
+    AstNode, BackrefAstNode, BlockExprNode, BlockExprTree, Footnote, PassedSyntaxTree, Route, Span,
+};
+
+/// The footnotes of the page being numbered.
+#[derive(Debug, Default)]
+struct PageFootnotes {
+    /// `[fn:label] definition` paragraphs on this page. The first one wins.
+    definitions: HashMap<String, BlockExprTree>,
+    footnotes: Vec<Footnote>,
+    /// label -> index into `footnotes`
+    numbered: HashMap<String, usize>,
+    /// Indices into `footnotes` whose definition came from `definitions`. Those were copied before
+    /// the references inside them got their numbers, so they are copied again once the page is done.
+    from_definitions: Vec<usize>,
+}
+
+impl PageFootnotes {
+    /// Count one more reference, numbering the footnote if this is its first.
+    /// Undefined footnotes get `None`.
+    fn reference(
+        &mut self,
+        label: Option<&str>,
+        inline: Option<&BlockExprTree>,
+    ) -> Option<(usize, usize)> {
+        let idx = match label.and_then(|label| self.numbered.get(label)) {
+            Some(&idx) => idx,
+            None => {
+                let idx = self.footnotes.len();
+                let definition = match (inline, label) {
+                    (Some(definition), _) => definition.clone(),
+                    (None, Some(label)) => {
+                        let definition = self.definitions.get(label)?.clone();
+                        self.from_definitions.push(idx);
+                        definition
+                    }
+                    (None, None) => return None,
+                };
+                self.footnotes.push(Footnote {
+                    number: idx + 1,
+                    label: label.map(str::to_string),
+                    definition,
+                    references: 0,
+                });
+                if let Some(label) = label {
+                    self.numbered.insert(label.to_string(), idx);
+                }
+                idx
+            }
+        };
+
+        let footnote = &mut self.footnotes[idx];
+        footnote.references += 1;
+        Some((footnote.number, footnote.references))
+    }
+}
+
+fn is_page(node: &BackrefAstNode) -> bool {
+    matches!(
+        &node.inner,
+        AstNode::Heading {
+            routing: Some(Route::Page(_)),
+            ..
+        }
+    )
+}
+
+/// Pages below this one have footnotes of their own.
+fn collect_definitions(nodes: &[BackrefAstNode], page: &mut PageFootnotes) {
+    for node in nodes.iter().filter(|node| !is_page(node)) {
+        if let AstNode::FootnoteDefinition { label, definition } = &node.inner {
+            page.definitions
+                .entry(label.clone())
+                .or_insert_with(|| definition.clone());
+        }
+        if let Some(children) = node.inner.children() {
+            collect_definitions(children, page);
+        }
+    }
+}
+
+fn number_bet(bet: &mut BlockExprTree, page: &mut PageFootnotes) {
+    for ben in bet {
+        let mut first_inline = None;
+        if let BlockExprNode::FootnoteRef {
+            label,
+            definition,
+            number,
+        } = &mut ben.inner
+        {
+            *number = page.reference(label.as_deref(), definition.as_ref());
+            if let (Some((footnote, 1)), Some(_)) = (*number, definition) {
+                first_inline = Some(footnote);
+            }
+        }
+        if let Some(children) = ben.inner.children_mut() {
+            number_bet(children, page);
+        }
+        // The list gets the inline definition with its own references numbered.
+        if let (
+            Some(footnote),
+            BlockExprNode::FootnoteRef {
+                definition: Some(definition),
+                ..
+            },
+        ) = (first_inline, &ben.inner)
+        {
+            page.footnotes[footnote - 1].definition = definition.clone();
+        }
+    }
+}
+
+/// Copy the `[fn:label]` definitions into the list again, now that the references inside them
+/// are numbered too.
+fn recopy_definitions(children: &[BackrefAstNode], page: &mut PageFootnotes) {
+    let mut numbered = PageFootnotes::default();
+    collect_definitions(children, &mut numbered);
+    for &idx in &page.from_definitions {
+        let footnote = &mut page.footnotes[idx];
+        if let Some(definition) = footnote
+            .label
+            .as_ref()
+            .and_then(|label| numbered.definitions.remove(label))
+        {
+            footnote.definition = definition;
+        }
+    }
+}
+
+fn number_footnotes(nodes: &mut [BackrefAstNode], mut page: Option<&mut PageFootnotes>) {
+    for node in nodes {
+        if is_page(node) {
+            let mut footnotes = PageFootnotes::default();
+            if let Some(children) = node.inner.children() {
+                collect_definitions(children, &mut footnotes);
+            }
+            for bet in node.inner.bets_mut() {
+                number_bet(bet, &mut footnotes);
+            }
+
+            let end = node.span.end;
+            if let Some(children) = node.inner.children_mut() {
+                number_footnotes(children, Some(&mut footnotes));
+                recopy_definitions(children, &mut footnotes);
+                if !footnotes.footnotes.is_empty() {
+                    children.push(BackrefAstNode {
+                        parent_idx: children.last().map_or(0, |last| last.parent_idx),
+                        inner: AstNode::Footnotes(footnotes.footnotes),
+                        render_group: node.render_group,
+                        span: Span { start: end, end },
+                    });
+                }
+            }
+            continue;
+        }
+
+        // Footnotes outside of any page have nowhere to go, so they stay unnumbered.
+        if let Some(page) = page.as_deref_mut() {
+            for bet in node.inner.bets_mut() {
+                number_bet(bet, page);
+            }
+        }
+        if let Some(children) = node.inner.children_mut() {
+            number_footnotes(children, page.as_deref_mut());
+        }
+    }
+}
+
+/// Number the footnotes of a tree that already went through [`pass4`] again, e.g. after
+/// [`crate::access::strip_restricted`] took subtrees out, so the lists at the end of each page
+/// only hold what is left and have no gaps.
+pub fn renumber_footnotes(nodes: &mut PassedSyntaxTree) {
+    remove_footnote_lists(nodes);
+    number_footnotes(nodes, None);
+}
+
+fn remove_footnote_lists(nodes: &mut Vec<BackrefAstNode>) {
+    nodes.retain(|node| !matches!(node.inner, AstNode::Footnotes(_)));
+    for node in nodes {
+        if let Some(children) = node.inner.children_mut() {
+            remove_footnote_lists(children);
+        }
+    }
+}
+
+/// Footnote pass: number footnotes per page in order of first use and list them at the end of the page.
+#[tracing::instrument(skip_all)]
+pub fn pass4(mut nodes: PassedSyntaxTree) -> Result<PassedSyntaxTree> {
+    number_footnotes(&mut nodes, None);
+
+    Ok(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_n_pass;
+
+    /// The numbers of the references in each listed definition of the first page
+    fn nested_numbers(input: &str) -> Vec<Vec<Option<(usize, usize)>>> {
+        let ast = parse_n_pass(input).unwrap();
+        let footnotes = ast[0]
+            .inner
+            .children()
+            .unwrap()
+            .iter()
+            .find_map(|node| match &node.inner {
+                AstNode::Footnotes(footnotes) => Some(footnotes.clone()),
+                _ => None,
+            })
+            .unwrap();
+        footnotes
+            .iter()
+            .map(|footnote| {
+                footnote
+                    .definition
+                    .iter()
+                    .filter_map(|node| match node.inner {
+                        BlockExprNode::FootnoteRef { number, .. } => Some(number),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn references_in_definitions_are_numbered() {
+        assert_eq!(
+            nested_numbers("* home :index:\na[fn:1]\n\n[fn:1] see[fn:2]\n\n[fn:2] two\n"),
+            vec![vec![Some((2, 1))], vec![]]
+        );
+        assert_eq!(
+            nested_numbers("* home :index:\nx[fn::outer[fn::inner]]\n"),
+            vec![vec![Some((2, 1))], vec![]]
+        );
+    }
+}
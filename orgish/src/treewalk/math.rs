@@ -0,0 +1,1055 @@
+//! LaTeX math to MathML, so formulas render without any JavaScript.
+//!
+//! Only the commonly used part of LaTeX is understood. Unknown commands show up as themselves in
+//! an `<merror>` and the rest of the formula still renders, and formulas that don't parse at all,
+//! like ones with a missing `}`, are shown as their source instead.
+
+use anyhow::{bail, Result};
+
+use super::escape_html;
+
+/// `$…$` is inline, `\[…\]`, `$$…$$` and environments are `display`.
+pub fn latex_to_mathml(latex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        tokens: tokenize(latex),
+        pos: 0,
+        variant: None,
+        depth: 0,
+    };
+    match parser.parse_row(Stop::End) {
+        Ok(row) => format!(
+            r#"<math display="{}"><semantics><mrow>{}</mrow><annotation encoding="application/x-tex">{}</annotation></semantics></math>"#,
+            if display { "block" } else { "inline" },
+            row.concat(),
+            escape_html(latex)
+        ),
+        Err(err) => format!(
+            r#"<code class="math-error" title="{}">{}</code>"#,
+            escape_html(&err.to_string()),
+            escape_html(latex)
+        ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// `\frac` is `Command("frac")`, `\,` is `Command(",")`
+    Command(String),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    /// `&`
+    Align,
+    /// Any amount of whitespace, which only matters in `\text{…}`
+    Space,
+    Char(char),
+}
+
+fn tokenize(latex: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = latex.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut name = c.to_string();
+                    while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                        name.push(c);
+                    }
+                    Token::Command(name)
+                }
+                Some(c) => Token::Command(c.to_string()),
+                None => Token::Char('\\'),
+            },
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '&' => Token::Align,
+            '%' => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+                continue;
+            }
+            c if c.is_whitespace() => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                Token::Space
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// What ends the row being parsed. The token it stops at is left for the caller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stop {
+    End,
+    /// `}`
+    Group,
+    /// `]`, for `\sqrt[n]{…}`
+    Bracket,
+    /// `\right`
+    Right,
+    /// `&`, `\\` or `\end`
+    Cell,
+}
+
+/// `\mathbb` and friends. MathML Core only knows `mathvariant="normal"`, so everything else is
+/// done with the Unicode mathematical alphanumerics instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variant {
+    Normal,
+    Bold,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+struct Atom {
+    mathml: String,
+    /// Scripts go above and below instead of to the side, like `\sum` and `\lim`
+    limits: bool,
+    /// `\sin` and friends get a little space after them.
+    function: bool,
+}
+
+impl Atom {
+    fn new(mathml: String) -> Self {
+        Atom {
+            mathml,
+            limits: false,
+            function: false,
+        }
+    }
+}
+
+/// Groups, fractions and environments nested deeper than this are shown as their source, so
+/// `{{{{…}}}}` can't run the parser out of stack. No real formula gets anywhere near it.
+const MAX_DEPTH: usize = 100;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    variant: Option<Variant>,
+    /// How many atoms are being parsed inside each other, see [`MAX_DEPTH`]
+    depth: usize,
+}
+
+impl Parser {
+    fn skip_spaces(&mut self) {
+        while self.tokens.get(self.pos) == Some(&Token::Space) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<Token> {
+        self.skip_spaces();
+        self.tokens.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            _ => bail!("expected {what}"),
+        }
+    }
+
+    fn parse_row(&mut self, stop: Stop) -> Result<Vec<String>> {
+        let mut row = vec![];
+        loop {
+            match (self.peek(), stop) {
+                (None, Stop::End) => break,
+                (None, _) => bail!("unexpected end of formula"),
+                (Some(Token::Close), Stop::Group) => break,
+                (Some(Token::Close), _) => bail!("unexpected }}"),
+                (Some(Token::Char(']')), Stop::Bracket) => break,
+                (Some(Token::Command(name)), Stop::Right) if name == "right" => break,
+                (Some(Token::Align), Stop::Cell) => break,
+                (Some(Token::Command(name)), Stop::Cell) if name == "\\" || name == "end" => break,
+                // Only mean something in environments
+                (Some(Token::Align), _) => self.pos += 1,
+                (Some(Token::Command(name)), _) if name == "\\" => self.pos += 1,
+                _ => {
+                    let atom = match self.peek() {
+                        // A script with nothing to attach to, like `{}^{14}C`
+                        Some(Token::Sup | Token::Sub) => Atom::new("<mrow></mrow>".to_string()),
+                        _ => self.parse_atom(false)?,
+                    };
+                    row.push(self.parse_scripts(atom.mathml, atom.limits)?);
+                    if atom.function {
+                        row.push(r#"<mspace width="0.1667em"></mspace>"#.to_string());
+                    }
+                }
+            }
+        }
+        Ok(row)
+    }
+
+    fn parse_scripts(&mut self, base: String, limits: bool) -> Result<String> {
+        let (mut sub, mut sup) = (None, None);
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_arg()?);
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_arg()?);
+                }
+                Some(Token::Char('\'')) if sup.is_none() => {
+                    let mut primes = String::new();
+                    while self.peek() == Some(Token::Char('\'')) {
+                        self.pos += 1;
+                        primes.push('′');
+                    }
+                    sup = Some(format!("<mo>{primes}</mo>"));
+                }
+                _ => break,
+            }
+        }
+
+        let (sub_tag, sup_tag, both_tag) = if limits {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{sub_tag}>{base}{sub}</{sub_tag}>"),
+            (None, Some(sup)) => format!("<{sup_tag}>{base}{sup}</{sup_tag}>"),
+            (Some(sub), Some(sup)) => format!("<{both_tag}>{base}{sub}{sup}</{both_tag}>"),
+        })
+    }
+
+    /// One argument of a command or script: a `{group}`, or a single token like TeX does, so
+    /// `x^23` is `x²3`.
+    fn parse_arg(&mut self) -> Result<String> {
+        match self.peek() {
+            None | Some(Token::Close) => bail!("expected an argument"),
+            _ => Ok(self.parse_atom(true)?.mathml),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<String> {
+        self.expect(Token::Open, "{")?;
+        let row = self.parse_row(Stop::Group)?;
+        self.expect(Token::Close, "}")?;
+        Ok(format!("<mrow>{}</mrow>", row.concat()))
+    }
+
+    /// The raw text of a `{group}`, for `\text{…}` and environment names
+    fn parse_text(&mut self) -> Result<String> {
+        self.expect(Token::Open, "{")?;
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.get(self.pos).cloned();
+            self.pos += 1;
+            match token {
+                None => bail!("expected }}"),
+                Some(Token::Close) if depth == 0 => break,
+                Some(Token::Close) => depth -= 1,
+                Some(Token::Open) => depth += 1,
+                Some(Token::Space) => text.push(' '),
+                Some(Token::Sup) => text.push('^'),
+                Some(Token::Sub) => text.push('_'),
+                Some(Token::Align) => text.push('&'),
+                Some(Token::Char(c)) => text.push(c),
+                Some(Token::Command(name)) => match &name[..] {
+                    "{" | "}" | "%" | "$" | "#" | "&" | "_" => text.push_str(&name),
+                    " " | "," | ";" | ":" | "quad" | "qquad" => text.push(' '),
+                    _ => {
+                        text.push('\\');
+                        text.push_str(&name);
+                    }
+                },
+            }
+        }
+        Ok(text)
+    }
+
+    /// Everything nested goes through here, so this is where the depth is counted.
+    fn parse_atom(&mut self, single: bool) -> Result<Atom> {
+        if self.depth == MAX_DEPTH {
+            bail!("formula is nested too deeply");
+        }
+        self.depth += 1;
+        let atom = self.parse_atom_inner(single);
+        self.depth -= 1;
+        atom
+    }
+
+    fn parse_atom_inner(&mut self, single: bool) -> Result<Atom> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => bail!("unexpected end of formula"),
+        };
+        Ok(match token {
+            Token::Open => {
+                self.pos -= 1;
+                Atom::new(self.parse_group()?)
+            }
+            Token::Char(c) if c.is_ascii_digit() || (c == '.' && self.next_is_digit()) => {
+                let mut number = c.to_string();
+                // Scripts only get the first digit, like in TeX.
+                while let Some(digit) = self.number_char().filter(|_| !single) {
+                    number.push(digit);
+                    self.pos += 1;
+                }
+                Atom::new(format!("<mn>{}</mn>", self.styled(&number)))
+            }
+            Token::Char(c) if c.is_alphabetic() => Atom::new(self.identifier(c)),
+            Token::Char(c) => Atom::new(operator(&match c {
+                '-' => '−'.to_string(),
+                '*' => '∗'.to_string(),
+                '~' => return Ok(Atom::new(space("0.3333em"))),
+                c => c.to_string(),
+            })),
+            Token::Command(name) => self.parse_command(&name)?,
+            Token::Sup | Token::Sub => bail!("double script"),
+            Token::Close | Token::Align | Token::Space => bail!("expected an argument"),
+        })
+    }
+
+    /// The next digit of a number, or its decimal point
+    fn number_char(&self) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Char(c)) if c.is_ascii_digit() => Some(*c),
+            Some(Token::Char('.')) if self.next_is_digit_at(self.pos + 1) => Some('.'),
+            _ => None,
+        }
+    }
+
+    fn next_is_digit(&self) -> bool {
+        self.next_is_digit_at(self.pos)
+    }
+
+    fn next_is_digit_at(&self, pos: usize) -> bool {
+        matches!(self.tokens.get(pos), Some(Token::Char(c)) if c.is_ascii_digit())
+    }
+
+    fn identifier(&self, c: char) -> String {
+        match self.variant {
+            Some(Variant::Normal) => format!(
+                r#"<mi mathvariant="normal">{}</mi>"#,
+                escape_html(&c.to_string())
+            ),
+            _ => format!("<mi>{}</mi>", self.styled(&c.to_string())),
+        }
+    }
+
+    fn styled(&self, s: &str) -> String {
+        match self.variant {
+            Some(variant) => escape_html(
+                &s.chars()
+                    .map(|c| styled_char(c, variant))
+                    .collect::<String>(),
+            ),
+            None => escape_html(s),
+        }
+    }
+
+    fn with_variant(&mut self, variant: Variant) -> Result<String> {
+        let outer = self.variant.replace(variant);
+        let arg = self.parse_arg();
+        self.variant = outer;
+        arg
+    }
+
+    /// `(` in `\left(`, `\{` in `\bigl\{`. `.` is no delimiter at all.
+    fn parse_delimiter(&mut self) -> Result<Option<String>> {
+        Ok(match self.next() {
+            Some(Token::Char('.')) => None,
+            Some(Token::Char(c)) => Some(c.to_string()),
+            Some(Token::Command(name)) => match &name[..] {
+                "{" | "lbrace" => Some("{".to_string()),
+                "}" | "rbrace" => Some("}".to_string()),
+                "|" | "Vert" => Some("‖".to_string()),
+                "vert" => Some("|".to_string()),
+                name => match symbol(name) {
+                    Some(Symbol::Operator(s)) => Some(s.to_string()),
+                    _ => bail!("expected a delimiter"),
+                },
+            },
+            _ => bail!("expected a delimiter"),
+        })
+    }
+
+    fn parse_command(&mut self, name: &str) -> Result<Atom> {
+        let accent = |mark: &str| format!(r#"<mo stretchy="false">{mark}</mo>"#);
+        Ok(Atom::new(match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                format!("<mfrac>{}{}</mfrac>", self.parse_arg()?, self.parse_arg()?)
+            }
+            "binom" | "dbinom" | "tbinom" => format!(
+                r#"<mrow><mo>(</mo><mfrac linethickness="0">{}{}</mfrac><mo>)</mo></mrow>"#,
+                self.parse_arg()?,
+                self.parse_arg()?
+            ),
+            "sqrt" => {
+                if self.peek() == Some(Token::Char('[')) {
+                    self.pos += 1;
+                    let index = self.parse_row(Stop::Bracket)?;
+                    self.pos += 1;
+                    let radicand = self.parse_arg()?;
+                    format!("<mroot>{radicand}<mrow>{}</mrow></mroot>", index.concat())
+                } else {
+                    format!("<msqrt>{}</msqrt>", self.parse_arg()?)
+                }
+            }
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let row = self.parse_row(Stop::Right)?;
+                self.pos += 1;
+                let close = self.parse_delimiter()?;
+                let fence = |delimiter: Option<String>| match delimiter {
+                    Some(d) => format!(
+                        r#"<mo fence="true" stretchy="true">{}</mo>"#,
+                        escape_html(&d)
+                    ),
+                    None => "".to_string(),
+                };
+                format!(
+                    "<mrow>{}{}{}</mrow>",
+                    fence(open),
+                    row.concat(),
+                    fence(close)
+                )
+            }
+            "right" => bail!(r"\right without \left"),
+            "big" | "bigl" | "bigr" | "bigm" | "Big" | "Bigl" | "Bigr" | "Bigm" | "bigg"
+            | "biggl" | "biggr" | "biggm" | "Bigg" | "Biggl" | "Biggr" | "Biggm" => {
+                let size = match name.trim_end_matches(|c| c == 'l' || c == 'r' || c == 'm') {
+                    "big" => "1.2em",
+                    "Big" => "1.8em",
+                    "bigg" => "2.4em",
+                    _ => "3em",
+                };
+                match self.parse_delimiter()? {
+                    Some(d) => format!(
+                        r#"<mo minsize="{size}" maxsize="{size}">{}</mo>"#,
+                        escape_html(&d)
+                    ),
+                    None => "".to_string(),
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" | "hbox" | "textup" => {
+                format!("<mtext>{}</mtext>", escape_html(&self.parse_text()?))
+            }
+            "textbf" | "textit" | "textsf" | "texttt" => {
+                let variant = match name {
+                    "textbf" => Some(Variant::Bold),
+                    "textsf" => Some(Variant::SansSerif),
+                    "texttt" => Some(Variant::Monospace),
+                    _ => None,
+                };
+                let text = self.parse_text()?;
+                let text = match variant {
+                    Some(variant) => text.chars().map(|c| styled_char(c, variant)).collect(),
+                    // Italic text is what math already looks like.
+                    None => text,
+                };
+                format!("<mtext>{}</mtext>", escape_html(&text))
+            }
+            "operatorname" => format!("<mi>{}</mi>", escape_html(&self.parse_text()?)),
+            "mathrm" | "mathup" => self.with_variant(Variant::Normal)?,
+            "mathit" => self.parse_arg()?,
+            "mathbf" | "boldsymbol" | "bm" => self.with_variant(Variant::Bold)?,
+            "mathbb" => self.with_variant(Variant::DoubleStruck)?,
+            "mathcal" | "mathscr" => self.with_variant(Variant::Script)?,
+            "mathfrak" => self.with_variant(Variant::Fraktur)?,
+            "mathsf" => self.with_variant(Variant::SansSerif)?,
+            "mathtt" => self.with_variant(Variant::Monospace)?,
+            "hat" | "widehat" => format!(
+                r#"<mover accent="true">{}{}</mover>"#,
+                self.parse_arg()?,
+                accent("^")
+            ),
+            "bar" | "overline" => format!(
+                r#"<mover accent="true">{}{}</mover>"#,
+                self.parse_arg()?,
+                accent("‾")
+            ),
+            "vec" | "overrightarrow" => format!(
+                r#"<mover accent="true">{}{}</mover>"#,
+                self.parse_arg()?,
+                accent("→")
+            ),
+            "dot" => format!(
+                r#"<mover accent="true">{}{}</mover>"#,
+                self.parse_arg()?,
+                accent("˙")
+            ),
+            "ddot" => format!(
+                r#"<mover accent="true">{}{}</mover>"#,
+                self.parse_arg()?,
+                accent("¨")
+            ),
+            "tilde" | "widetilde" => format!(
+                r#"<mover accent="true">{}{}</mover>"#,
+                self.parse_arg()?,
+                accent("~")
+            ),
+            "underline" => format!(
+                r#"<munder accentunder="true">{}{}</munder>"#,
+                self.parse_arg()?,
+                accent("_")
+            ),
+            "overbrace" => {
+                return Ok(Atom {
+                    mathml: format!("<mover>{}<mo>⏞</mo></mover>", self.parse_arg()?),
+                    limits: true,
+                    function: false,
+                })
+            }
+            "underbrace" => {
+                return Ok(Atom {
+                    mathml: format!("<munder>{}<mo>⏟</mo></munder>", self.parse_arg()?),
+                    limits: true,
+                    function: false,
+                })
+            }
+            // A slash through the relation, which fonts show as `≠` for `\not=`.
+            "not" => {
+                let negated = self.parse_atom(true)?.mathml;
+                match negated.strip_prefix("<mo>") {
+                    Some(rest) => format!("<mo>{}\u{338}</mo>", rest.trim_end_matches("</mo>")),
+                    None => negated,
+                }
+            }
+            "begin" => self.parse_environment()?,
+            "end" => bail!(r"\end without \begin"),
+            "label" | "tag" => {
+                self.parse_text()?;
+                "".to_string()
+            }
+            "middle" => match self.parse_delimiter()? {
+                Some(d) => format!(r#"<mo stretchy="true">{}</mo>"#, escape_html(&d)),
+                None => "".to_string(),
+            },
+            "displaystyle" | "textstyle" | "limits" | "nolimits" | "nonumber" | "notag" => {
+                "".to_string()
+            }
+            "," | "thinspace" => space("0.1667em"),
+            ":" | ">" | "medspace" => space("0.2222em"),
+            ";" | "thickspace" => space("0.2778em"),
+            "!" | "negthinspace" => space("-0.1667em"),
+            " " => space("0.3333em"),
+            "quad" => space("1em"),
+            "qquad" => space("2em"),
+            "{" | "lbrace" => r#"<mo stretchy="false">{</mo>"#.to_string(),
+            "}" | "rbrace" => r#"<mo stretchy="false">}</mo>"#.to_string(),
+            "|" | "Vert" => "<mo>‖</mo>".to_string(),
+            "%" | "$" | "#" | "&" | "_" => format!("<mi>{}</mi>", escape_html(name)),
+            name => match symbol(name) {
+                Some(Symbol::Identifier(s)) => format!("<mi>{s}</mi>"),
+                Some(Symbol::Operator(s)) => operator(s),
+                Some(Symbol::BigOperator(s)) => {
+                    return Ok(Atom {
+                        mathml: format!(r#"<mo movablelimits="true">{s}</mo>"#),
+                        limits: true,
+                        function: false,
+                    })
+                }
+                Some(Symbol::Integral(s)) => format!("<mo>{s}</mo>"),
+                Some(Symbol::Function) => {
+                    return Ok(Atom {
+                        mathml: format!("<mi>{name}</mi>"),
+                        limits: false,
+                        function: true,
+                    })
+                }
+                Some(Symbol::LimitFunction) => {
+                    return Ok(Atom {
+                        mathml: format!(r#"<mo movablelimits="true" form="prefix">{name}</mo>"#),
+                        limits: true,
+                        function: true,
+                    })
+                }
+                // The readable fallback: the command as it was written, the rest still renders.
+                None => format!(r"<merror><mtext>\{}</mtext></merror>", escape_html(name)),
+            },
+        }))
+    }
+
+    /// `\begin{name}…\end{name}`, the `\begin` is already eaten.
+    fn parse_environment(&mut self) -> Result<String> {
+        let name = self.parse_text()?;
+        let columns = match &name[..] {
+            // The column spec, `{lcr}`, or `{2}` columns for alignat
+            "array" | "alignat" | "alignat*" | "alignedat" => {
+                let spec = self.parse_text()?;
+                spec.chars()
+                    .filter_map(|c| match c {
+                        'l' => Some("left"),
+                        'c' => Some("center"),
+                        'r' => Some("right"),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            }
+            _ => vec![],
+        };
+        let rows = self.parse_table()?;
+
+        match self.next() {
+            Some(Token::Command(end)) if end == "end" => {}
+            _ => bail!(r"expected \end{{{name}}}"),
+        }
+        if self.parse_text()? != name {
+            bail!(r"expected \end{{{name}}}");
+        }
+
+        let table = |columnalign: &str| {
+            let mut table = format!(r#"<mtable columnalign="{columnalign}">"#);
+            for row in &rows {
+                table.push_str("<mtr>");
+                for cell in row {
+                    table.push_str(&format!("<mtd><mrow>{cell}</mrow></mtd>"));
+                }
+                table.push_str("</mtr>");
+            }
+            table.push_str("</mtable>");
+            table
+        };
+        let fenced = |open: &str, close: &str| {
+            format!(
+                r#"<mrow><mo fence="true">{open}</mo>{}<mo fence="true">{close}</mo></mrow>"#,
+                table("center")
+            )
+        };
+        Ok(match &name[..] {
+            // One formula, possibly broken over lines
+            "equation" | "equation*" | "displaymath" | "math" | "multline" | "multline*" => {
+                match &rows[..] {
+                    [row] if row.len() == 1 => row[0].clone(),
+                    _ => table("center"),
+                }
+            }
+            // Pairs of right and left aligned columns around the `&`s
+            "align" | "align*" | "aligned" | "split" | "alignat" | "alignat*" | "alignedat"
+            | "eqnarray" | "eqnarray*" => table("right left right left right left"),
+            "gather" | "gather*" | "gathered" => table("center"),
+            "matrix" | "smallmatrix" => table("center"),
+            "pmatrix" => fenced("(", ")"),
+            "bmatrix" => fenced("[", "]"),
+            "Bmatrix" => fenced("{", "}"),
+            "vmatrix" => fenced("|", "|"),
+            "Vmatrix" => fenced("‖", "‖"),
+            "cases" => format!(
+                r#"<mrow><mo fence="true">{{</mo>{}</mrow>"#,
+                table("left left")
+            ),
+            "array" => table(&columns.join(" ")),
+            name => format!(
+                r"<merror><mtext>\begin{{{}}}</mtext></merror>{}",
+                escape_html(name),
+                table("center")
+            ),
+        })
+    }
+
+    /// Rows of cells separated by `&` and `\\`, up to the `\end`
+    fn parse_table(&mut self) -> Result<Vec<Vec<String>>> {
+        let mut rows = vec![];
+        let mut row = vec![];
+        loop {
+            row.push(self.parse_row(Stop::Cell)?.concat());
+            match self.peek() {
+                Some(Token::Align) => self.pos += 1,
+                Some(Token::Command(name)) if name == "\\" => {
+                    self.pos += 1;
+                    // `\\[2pt]` asks for extra space, which tables already have enough of.
+                    if self.peek() == Some(Token::Char('[')) {
+                        while !matches!(self.next(), Some(Token::Char(']')) | None) {}
+                    }
+                    rows.push(std::mem::take(&mut row));
+                }
+                _ => break,
+            }
+        }
+        // A `\\` at the end of the last row doesn't start another one.
+        if row.len() > 1 || row.iter().any(|cell| !cell.is_empty()) || rows.is_empty() {
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+fn operator(s: &str) -> String {
+    match s {
+        // Plain brackets are as tall as the text, `\left` and `\right` are for stretching.
+        "(" | ")" | "[" | "]" | "|" | "⟨" | "⟩" | "⌈" | "⌉" | "⌊" | "⌋" => {
+            format!(r#"<mo stretchy="false">{}</mo>"#, escape_html(s))
+        }
+        _ => format!("<mo>{}</mo>", escape_html(s)),
+    }
+}
+
+fn space(width: &str) -> String {
+    format!(r#"<mspace width="{width}"></mspace>"#)
+}
+
+enum Symbol {
+    Identifier(&'static str),
+    Operator(&'static str),
+    /// `\sum`, whose limits go above and below in display math
+    BigOperator(&'static str),
+    /// `\int`, whose limits always go to the side
+    Integral(&'static str),
+    /// `\sin`, written as its name
+    Function,
+    /// `\lim`, written as its name with the limits below in display math
+    LimitFunction,
+}
+
+fn symbol(name: &str) -> Option<Symbol> {
+    use Symbol::*;
+    Some(match name {
+        "alpha" => Identifier("α"),
+        "beta" => Identifier("β"),
+        "gamma" => Identifier("γ"),
+        "delta" => Identifier("δ"),
+        "epsilon" => Identifier("ϵ"),
+        "varepsilon" => Identifier("ε"),
+        "zeta" => Identifier("ζ"),
+        "eta" => Identifier("η"),
+        "theta" => Identifier("θ"),
+        "vartheta" => Identifier("ϑ"),
+        "iota" => Identifier("ι"),
+        "kappa" => Identifier("κ"),
+        "lambda" => Identifier("λ"),
+        "mu" => Identifier("μ"),
+        "nu" => Identifier("ν"),
+        "xi" => Identifier("ξ"),
+        "pi" => Identifier("π"),
+        "varpi" => Identifier("ϖ"),
+        "rho" => Identifier("ρ"),
+        "varrho" => Identifier("ϱ"),
+        "sigma" => Identifier("σ"),
+        "varsigma" => Identifier("ς"),
+        "tau" => Identifier("τ"),
+        "upsilon" => Identifier("υ"),
+        "phi" => Identifier("ϕ"),
+        "varphi" => Identifier("φ"),
+        "chi" => Identifier("χ"),
+        "psi" => Identifier("ψ"),
+        "omega" => Identifier("ω"),
+        "Gamma" => Identifier("Γ"),
+        "Delta" => Identifier("Δ"),
+        "Theta" => Identifier("Θ"),
+        "Lambda" => Identifier("Λ"),
+        "Xi" => Identifier("Ξ"),
+        "Pi" => Identifier("Π"),
+        "Sigma" => Identifier("Σ"),
+        "Upsilon" => Identifier("Υ"),
+        "Phi" => Identifier("Φ"),
+        "Psi" => Identifier("Ψ"),
+        "Omega" => Identifier("Ω"),
+        "infty" => Identifier("∞"),
+        "partial" => Identifier("∂"),
+        "nabla" => Identifier("∇"),
+        "emptyset" | "varnothing" => Identifier("∅"),
+        "hbar" => Identifier("ℏ"),
+        "ell" => Identifier("ℓ"),
+        "Re" => Identifier("ℜ"),
+        "Im" => Identifier("ℑ"),
+        "aleph" => Identifier("ℵ"),
+        "wp" => Identifier("℘"),
+        "top" => Identifier("⊤"),
+        "bot" => Identifier("⊥"),
+        "angle" => Identifier("∠"),
+        "triangle" => Identifier("△"),
+        "prime" => Operator("′"),
+        "times" => Operator("×"),
+        "cdot" => Operator("⋅"),
+        "pm" => Operator("±"),
+        "mp" => Operator("∓"),
+        "div" => Operator("÷"),
+        "ast" => Operator("∗"),
+        "star" => Operator("⋆"),
+        "circ" => Operator("∘"),
+        "bullet" => Operator("∙"),
+        "oplus" => Operator("⊕"),
+        "ominus" => Operator("⊖"),
+        "otimes" => Operator("⊗"),
+        "odot" => Operator("⊙"),
+        "cup" => Operator("∪"),
+        "cap" => Operator("∩"),
+        "setminus" => Operator("∖"),
+        "wedge" | "land" => Operator("∧"),
+        "vee" | "lor" => Operator("∨"),
+        "neg" | "lnot" => Operator("¬"),
+        "forall" => Operator("∀"),
+        "exists" => Operator("∃"),
+        "nexists" => Operator("∄"),
+        "leq" | "le" => Operator("≤"),
+        "geq" | "ge" => Operator("≥"),
+        "neq" | "ne" => Operator("≠"),
+        "ll" => Operator("≪"),
+        "gg" => Operator("≫"),
+        "approx" => Operator("≈"),
+        "equiv" => Operator("≡"),
+        "sim" => Operator("∼"),
+        "simeq" => Operator("≃"),
+        "cong" => Operator("≅"),
+        "propto" => Operator("∝"),
+        "prec" => Operator("≺"),
+        "succ" => Operator("≻"),
+        "preceq" => Operator("⪯"),
+        "succeq" => Operator("⪰"),
+        "in" => Operator("∈"),
+        "notin" => Operator("∉"),
+        "ni" => Operator("∋"),
+        "subset" => Operator("⊂"),
+        "subseteq" => Operator("⊆"),
+        "supset" => Operator("⊃"),
+        "supseteq" => Operator("⊇"),
+        "mid" => Operator("∣"),
+        "nmid" => Operator("∤"),
+        "parallel" => Operator("∥"),
+        "perp" => Operator("⊥"),
+        "vdash" => Operator("⊢"),
+        "models" => Operator("⊨"),
+        "to" | "rightarrow" => Operator("→"),
+        "gets" | "leftarrow" => Operator("←"),
+        "leftrightarrow" => Operator("↔"),
+        "Rightarrow" => Operator("⇒"),
+        "Leftarrow" => Operator("⇐"),
+        "Leftrightarrow" => Operator("⇔"),
+        "implies" => Operator("⟹"),
+        "impliedby" => Operator("⟸"),
+        "iff" => Operator("⟺"),
+        "mapsto" => Operator("↦"),
+        "longrightarrow" => Operator("⟶"),
+        "longleftarrow" => Operator("⟵"),
+        "longmapsto" => Operator("⟼"),
+        "uparrow" => Operator("↑"),
+        "downarrow" => Operator("↓"),
+        "hookrightarrow" => Operator("↪"),
+        "ldots" | "dots" | "dotsc" | "dotso" => Operator("…"),
+        "cdots" | "dotsb" | "dotsm" => Operator("⋯"),
+        "vdots" => Operator("⋮"),
+        "ddots" => Operator("⋱"),
+        "langle" => Operator("⟨"),
+        "rangle" => Operator("⟩"),
+        "lceil" => Operator("⌈"),
+        "rceil" => Operator("⌉"),
+        "lfloor" => Operator("⌊"),
+        "rfloor" => Operator("⌋"),
+        "vert" => Operator("|"),
+        "backslash" => Operator("∖"),
+        "colon" => Operator(":"),
+        "sum" => BigOperator("∑"),
+        "prod" => BigOperator("∏"),
+        "coprod" => BigOperator("∐"),
+        "bigcup" => BigOperator("⋃"),
+        "bigcap" => BigOperator("⋂"),
+        "bigoplus" => BigOperator("⨁"),
+        "bigotimes" => BigOperator("⨂"),
+        "bigvee" => BigOperator("⋁"),
+        "bigwedge" => BigOperator("⋀"),
+        "int" => Integral("∫"),
+        "iint" => Integral("∬"),
+        "iiint" => Integral("∭"),
+        "oint" => Integral("∮"),
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan" | "sinh"
+        | "cosh" | "tanh" | "coth" | "log" | "ln" | "lg" | "exp" | "deg" | "dim" | "ker"
+        | "hom" | "arg" => Function,
+        "lim" | "limsup" | "liminf" | "max" | "min" | "sup" | "inf" | "det" | "gcd" | "Pr" => {
+            LimitFunction
+        }
+        _ => return None,
+    })
+}
+
+/// `c` in the mathematical alphanumerics block, or `c` itself when there is no styled version.
+fn styled_char(c: char, variant: Variant) -> char {
+    // Letters that were in Unicode before the block, and are left out of it
+    let exception = match (variant, c) {
+        (Variant::DoubleStruck, 'C') => Some('ℂ'),
+        (Variant::DoubleStruck, 'H') => Some('ℍ'),
+        (Variant::DoubleStruck, 'N') => Some('ℕ'),
+        (Variant::DoubleStruck, 'P') => Some('ℙ'),
+        (Variant::DoubleStruck, 'Q') => Some('ℚ'),
+        (Variant::DoubleStruck, 'R') => Some('ℝ'),
+        (Variant::DoubleStruck, 'Z') => Some('ℤ'),
+        (Variant::Script, 'B') => Some('ℬ'),
+        (Variant::Script, 'E') => Some('ℰ'),
+        (Variant::Script, 'F') => Some('ℱ'),
+        (Variant::Script, 'H') => Some('ℋ'),
+        (Variant::Script, 'I') => Some('ℐ'),
+        (Variant::Script, 'L') => Some('ℒ'),
+        (Variant::Script, 'M') => Some('ℳ'),
+        (Variant::Script, 'R') => Some('ℛ'),
+        (Variant::Script, 'e') => Some('ℯ'),
+        (Variant::Script, 'g') => Some('ℊ'),
+        (Variant::Script, 'o') => Some('ℴ'),
+        (Variant::Fraktur, 'C') => Some('ℭ'),
+        (Variant::Fraktur, 'H') => Some('ℌ'),
+        (Variant::Fraktur, 'I') => Some('ℑ'),
+        (Variant::Fraktur, 'R') => Some('ℜ'),
+        (Variant::Fraktur, 'Z') => Some('ℨ'),
+        _ => None,
+    };
+    if let Some(c) = exception {
+        return c;
+    }
+
+    // (capital A, small a, digit 0)
+    let (upper, lower, digit) = match variant {
+        Variant::Normal => return c,
+        Variant::Bold => (0x1D400, 0x1D41A, Some(0x1D7CE)),
+        Variant::Script => (0x1D49C, 0x1D4B6, None),
+        Variant::Fraktur => (0x1D504, 0x1D51E, None),
+        Variant::DoubleStruck => (0x1D538, 0x1D552, Some(0x1D7D8)),
+        Variant::SansSerif => (0x1D5A0, 0x1D5BA, Some(0x1D7E2)),
+        Variant::Monospace => (0x1D670, 0x1D68A, Some(0x1D7F6)),
+    };
+    let code = match c {
+        'A'..='Z' => upper + (c as u32 - 'A' as u32),
+        'a'..='z' => lower + (c as u32 - 'a' as u32),
+        '0'..='9' => match digit {
+            Some(digit) => digit + (c as u32 - '0' as u32),
+            None => return c,
+        },
+        _ => return c,
+    };
+    char::from_u32(code).unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What goes inside the `<math>`, without the wrapping and the annotation
+    fn mathml(latex: &str) -> String {
+        let math = latex_to_mathml(latex, false);
+        let row = math
+            .strip_prefix(r#"<math display="inline"><semantics><mrow>"#)
+            .and_then(|rest| rest.split_once(r#"</mrow><annotation"#))
+            .map(|(row, _)| row.to_string());
+        row.unwrap_or_else(|| panic!("{latex:?} didn't render: {math}"))
+    }
+
+    #[test]
+    fn display() {
+        assert!(latex_to_mathml("x", true).starts_with(r#"<math display="block">"#));
+        assert!(latex_to_mathml("a < b", false).ends_with(
+            r#"<annotation encoding="application/x-tex">a &lt; b</annotation></semantics></math>"#
+        ));
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(
+            mathml(r"\frac{a}{b}"),
+            "<mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac>"
+        );
+        // Arguments without braces are single tokens, like in TeX.
+        assert_eq!(mathml(r"\frac12"), "<mfrac><mn>1</mn><mn>2</mn></mfrac>");
+        assert_eq!(
+            mathml(r"\frac{\frac1x}y"),
+            "<mfrac><mrow><mfrac><mn>1</mn><mi>x</mi></mfrac></mrow><mi>y</mi></mfrac>"
+        );
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(
+            mathml("x_i^2"),
+            "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>"
+        );
+        assert_eq!(
+            mathml("x^23"),
+            "<msup><mi>x</mi><mn>2</mn></msup><mn>3</mn>"
+        );
+        assert_eq!(mathml("f'"), "<msup><mi>f</mi><mo>′</mo></msup>");
+        assert_eq!(
+            mathml(r"\sum_{i=0}^n"),
+            r#"<munderover><mo movablelimits="true">∑</mo><mrow><mi>i</mi><mo>=</mo><mn>0</mn></mrow><mi>n</mi></munderover>"#
+        );
+    }
+
+    #[test]
+    fn environments() {
+        assert_eq!(mathml(r"\begin{equation}x\end{equation}"), "<mi>x</mi>");
+        assert_eq!(
+            mathml(r"\begin{pmatrix}a & b\\c & d\end{pmatrix}"),
+            concat!(
+                r#"<mrow><mo fence="true">(</mo><mtable columnalign="center">"#,
+                "<mtr><mtd><mrow><mi>a</mi></mrow></mtd><mtd><mrow><mi>b</mi></mrow></mtd></mtr>",
+                "<mtr><mtd><mrow><mi>c</mi></mrow></mtd><mtd><mrow><mi>d</mi></mrow></mtd></mtr>",
+                r#"</mtable><mo fence="true">)</mo></mrow>"#
+            )
+        );
+        // A `\\` after the last row doesn't add an empty one.
+        assert_eq!(
+            mathml(r"\begin{gather}a\\\end{gather}"),
+            r#"<mtable columnalign="center"><mtr><mtd><mrow><mi>a</mi></mrow></mtd></mtr></mtable>"#
+        );
+    }
+
+    #[test]
+    fn unknown_commands_stay_readable() {
+        assert_eq!(
+            mathml(r"\foo + 1"),
+            "<merror><mtext>\\foo</mtext></merror><mo>+</mo><mn>1</mn>"
+        );
+        assert_eq!(
+            mathml(r"\begin{foo}x\end{foo}"),
+            concat!(
+                r"<merror><mtext>\begin{foo}</mtext></merror>",
+                r#"<mtable columnalign="center"><mtr><mtd><mrow><mi>x</mi></mrow></mtd></mtr></mtable>"#
+            )
+        );
+    }
+
+    #[test]
+    fn broken_formulas_are_shown_as_source() {
+        assert_eq!(
+            latex_to_mathml(r"\frac{a}{b", false),
+            r#"<code class="math-error" title="unexpected end of formula">\frac{a}{b</code>"#
+        );
+        assert_eq!(
+            latex_to_mathml(r"\begin{matrix}a\end{pmatrix}", false),
+            r#"<code class="math-error" title="expected \end{matrix}">\begin{matrix}a\end{pmatrix}</code>"#
+        );
+        assert_eq!(
+            latex_to_mathml("a}<b", false),
+            r#"<code class="math-error" title="unexpected }">a}&lt;b</code>"#
+        );
+    }
+
+    #[test]
+    fn deep_nesting_is_shown_as_source() {
+        let too_deep = |latex: &str| {
+            format!(
+                r#"<code class="math-error" title="formula is nested too deeply">{}</code>"#,
+                escape_html(latex)
+            )
+        };
+        let braces = format!("{}x{}", "{".repeat(100_000), "}".repeat(100_000));
+        assert_eq!(latex_to_mathml(&braces, false), too_deep(&braces));
+        let fracs = format!("{}1", r"\frac1".repeat(100_000));
+        assert_eq!(latex_to_mathml(&fracs, false), too_deep(&fracs));
+
+        let nested = format!(
+            "{}x{}",
+            "{".repeat(MAX_DEPTH - 1),
+            "}".repeat(MAX_DEPTH - 1)
+        );
+        assert!(mathml(&nested).contains("<mi>x</mi>"));
+    }
+}
@@ -1,27 +1,39 @@
 ///
 /// This module walks the AST and outputs HTML.
-/// It is all BAD and EVIL since it assumes the input is safe. This is okay for now, but TODO.
+/// Text and attribute values from the document are always escaped, so the input does not need to be trusted.
+/// The only exception is explicit raw HTML: `#+BEGIN_EXPORT html`, `#+HTML:` and `@@html:…@@`.
 ///
 use anyhow::{anyhow, bail, Result};
 use syntect::{highlighting::ThemeSet, html::highlighted_html_for_string, parsing::SyntaxSet};
 use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
 use tracing::trace;
 
 use crate::parse::{
-    AstNode, BackrefAstNode, BlockExprNode, BlockExprTree, BlockType, Directive, LinkTarget,
-    OutputTo, PassedSyntaxTree, RenderGroup, Route,
+    is_image_url, page_option, safe_url, slugify, toc_entries, AstNode, BackrefAstNode,
+    BlockExprNode, BlockExprTree, BlockType, Checkbox, ColumnAlignment, Directive, LinkTarget,
+    ListBullet, ListKind, OutputTo, PassedSyntaxTree, RenderGroup, Route, Spanned, TableRow,
+    TocEntry,
 };
 
+mod math;
+
+use self::math::latex_to_mathml;
+
 #[derive(Default, Debug)]
 pub struct ParseBuffers {
     pub main: String,
     pub nav: Vec<String>,
 }
 
-pub fn ast_to_html_string(nodes: &PassedSyntaxTree, to: OutputTo) -> Result<ParseBuffers> {
+pub fn ast_to_html_string(
+    nodes: &PassedSyntaxTree,
+    to: OutputTo,
+    images: &ImageInfos,
+) -> Result<ParseBuffers> {
     let mut buffers = ParseBuffers::default();
     for node in nodes {
-        let (mut htmls, res_to) = match ast_node_to_html_string(node, to)? {
+        let (mut htmls, res_to) = match ast_node_to_html_string(node, to, images)? {
             NodeToHtmlResult::Single(html, res_to) => (vec![html], res_to),
             NodeToHtmlResult::Many(htmls, res_to) => (htmls, res_to),
         };
@@ -56,8 +68,117 @@ lazy_static! {
     static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
 }
 
-#[tracing::instrument]
-fn ast_node_to_html_string(node: &BackrefAstNode, to: OutputTo) -> Result<NodeToHtmlResult> {
+/// What whoever hosts an image knows about it, see [`ImageInfos`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    /// (url, width) of smaller copies, for `srcset`
+    pub variants: Vec<(String, u32)>,
+}
+
+/// [`ImageInfo`] by `src`, so images get their size and smaller copies when rendered.
+/// The parser has no idea where images live, so images that aren't in here only get their `src`.
+/// [`image_srcs`] lists what to look up.
+pub type ImageInfos = HashMap<String, ImageInfo>;
+
+/// The `src` of every image in `nodes`, each once.
+pub fn image_srcs(nodes: &[BackrefAstNode]) -> HashSet<String> {
+    fn bet_srcs(bet: &BlockExprTree, srcs: &mut HashSet<String>) {
+        for ben in bet {
+            if let BlockExprNode::Link(LinkTarget::External(url), None) = &ben.inner {
+                if is_image_url(url) {
+                    srcs.insert(url.clone());
+                }
+            }
+            if let Some(children) = ben.inner.children() {
+                bet_srcs(children, srcs);
+            }
+        }
+    }
+
+    let mut srcs = HashSet::new();
+    for node in nodes {
+        if let AstNode::Figure { src, .. } = &node.inner {
+            srcs.insert(src.clone());
+        }
+        for bet in node.inner.bets() {
+            bet_srcs(bet, &mut srcs);
+        }
+        if let Some(children) = node.inner.children() {
+            srcs.extend(image_srcs(children));
+        }
+    }
+    srcs
+}
+
+/// An `<img>` for `src`. `attrs` come from `#+ATTR_HTML:` and win over what we would add.
+fn img_tag(src: &str, attrs: &[(String, String)], images: &ImageInfos) -> String {
+    let info = images.get(src);
+    let attr = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+
+    let mut img = format!(r#"<img src="{}""#, escape_html(&safe_url(src)));
+    // Event handlers and another src would get around the escaping.
+    for (key, value) in attrs.iter().filter(|(key, _)| {
+        key != "src"
+            && !key.starts_with("on")
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    }) {
+        img.push_str(&format!(r#" {}="{}""#, key, escape_html(value)));
+    }
+    if attr("alt").is_none() {
+        img.push_str(r#" alt="""#);
+    }
+
+    if let Some(info) = info {
+        // Knowing the size up front keeps the page from jumping around while the image loads.
+        match (attr("width"), attr("height")) {
+            (None, None) => img.push_str(&format!(
+                r#" width="{}" height="{}""#,
+                info.width, info.height
+            )),
+            (Some(width), None) if info.width != 0 => {
+                if let Ok(width) = width.parse::<u32>() {
+                    img.push_str(&format!(
+                        r#" height="{}""#,
+                        u64::from(width) * u64::from(info.height) / u64::from(info.width)
+                    ));
+                }
+            }
+            _ => {}
+        }
+
+        if !info.variants.is_empty() && attr("srcset").is_none() {
+            let srcset = info
+                .variants
+                .iter()
+                .map(|(url, width)| format!("{} {width}w", safe_url(url)))
+                .chain([format!("{} {}w", safe_url(src), info.width)])
+                .collect::<Vec<_>>()
+                .join(", ");
+            img.push_str(&format!(r#" srcset="{}""#, escape_html(&srcset)));
+            if attr("sizes").is_none() {
+                img.push_str(&format!(
+                    r#" sizes="(max-width: {width}px) 100vw, {width}px""#,
+                    width = info.width
+                ));
+            }
+        }
+    }
+
+    if attr("loading").is_none() {
+        img.push_str(r#" loading="lazy""#);
+    }
+    img.push('>');
+    img
+}
+
+#[tracing::instrument(skip(images))]
+fn ast_node_to_html_string(
+    node: &BackrefAstNode,
+    to: OutputTo,
+    images: &ImageInfos,
+) -> Result<NodeToHtmlResult> {
     let defr = to.is_using_default_rendering();
     let nav = to == OutputTo::Nav;
 
@@ -67,47 +188,82 @@ fn ast_node_to_html_string(node: &BackrefAstNode, to: OutputTo) -> Result<NodeTo
             children,
             level,
             title,
-            routing, // TODO use this to link?
+            routing,
+            anchor,
         } => match OutputTo::from_route(routing.clone()) {
             Some(OutputTo::Main) | None => NodeToHtmlResult::Single(
                 format!(
                     // In HTML headings do not have children as in our AST.
                     "<h{level} {id}>{title}</h{level}>{children}",
                     level = level,
-                    title = bet_to_html_string(title)?,
-                    children =
-                        ast_to_html_string(children, OutputTo::Main)?.output(&OutputTo::Main),
-                    id = match routing {
-                        Some(_route) => "TODO".to_string(),
+                    title = bet_to_html_string(title, images)?,
+                    children = ast_to_html_string(children, OutputTo::Main, images)?
+                        .output(&OutputTo::Main),
+                    id = match anchor {
+                        Some(anchor) => format!(r#"id="{}""#, escape_html(anchor)),
                         None => "".to_string(),
                     }
                 ),
                 OutputTo::Main,
             ),
             Some(OutputTo::Nav) => NodeToHtmlResult::Many(
-                ast_to_html_string(children, OutputTo::Nav)?.nav,
+                ast_to_html_string(children, OutputTo::Nav, images)?.nav,
                 OutputTo::Nav,
             ),
         },
 
         //  nav; special navbar rendering
-        AstNode::ListItem(_, ast) if nav => NodeToHtmlResult::Single(
-            format!(
-                "{}",
-                ast_to_html_string(ast, to)?.output(&to)
-            ),
+        AstNode::List(_, items) if nav => NodeToHtmlResult::Many(
+            items
+                .iter()
+                .map(|item| match &item.inner {
+                    AstNode::ListItem { children, .. } => {
+                        Ok(ast_to_html_string(children, to, images)?.output(&to))
+                    }
+                    other => Err(anyhow!("{}: illegal node {:?} in list", item.span, other)),
+                })
+                .collect::<Result<Vec<_>>>()?,
             to,
         ),
 
         AstNode::Block((_, bet)) if nav => {
-            NodeToHtmlResult::Single(bet_to_html_string(bet)?, to)
+            NodeToHtmlResult::Single(bet_to_html_string(bet, images)?, to)
         }
 
+        // Only flagged in the main output, a broken nav link is just left out.
+        AstNode::Error { .. } if nav => NodeToHtmlResult::Many(vec![], to),
+
+        AstNode::FootnoteDefinition { .. } | AstNode::Footnotes(_) if nav => {
+            NodeToHtmlResult::Many(vec![], to)
+        }
+
+        // The navbar is made of links, so the rest of what can be in a nav section is left out.
+        AstNode::Quote(_)
+        | AstNode::Center(_)
+        | AstNode::Verse(_)
+        | AstNode::Example(_)
+        | AstNode::Figure { .. }
+        | AstNode::Table { .. }
+        | AstNode::Directive(_)
+        | AstNode::HorizRule
+        | AstNode::WarningBlock(_)
+        | AstNode::SourceBlock { .. }
+            if nav =>
+        {
+            NodeToHtmlResult::Many(vec![], to)
+        }
+
+        // Hand-written HTML is the one thing that isn't escaped.
+        AstNode::Export { backend, content } if backend == "html" => {
+            NodeToHtmlResult::Single(content.clone(), to)
+        }
+        AstNode::Export { .. } => NodeToHtmlResult::Single("".to_string(), to),
 
         //  main; normal html rendering
         AstNode::Directive(d) if defr => NodeToHtmlResult::Single(
             match d {
-                Directive::Raw(_, _) => unreachable!(),
+                Directive::Raw(..) => unreachable!(),
+                Directive::Toc { entries, .. } => toc_to_html_string(entries),
                 // TODO Meh, maybe return Result<Option<String>>
                 _ => "".to_string(),
             },
@@ -115,26 +271,128 @@ fn ast_node_to_html_string(node: &BackrefAstNode, to: OutputTo) -> Result<NodeTo
         ),
 
         AstNode::Block((BlockType::Block, bet)) if defr => {
-            NodeToHtmlResult::Single(format!("<p>{}</p>", bet_to_html_string(bet)?), to)
+            NodeToHtmlResult::Single(format!("<p>{}</p>", bet_to_html_string(bet, images)?), to)
         }
 
         AstNode::Block((BlockType::Inline, bet)) if defr => {
-            NodeToHtmlResult::Single(bet_to_html_string(bet)?, to)
+            NodeToHtmlResult::Single(bet_to_html_string(bet, images)?, to)
+        }
+
+        AstNode::List(kind, items) if defr => {
+            NodeToHtmlResult::Single(list_to_html_string(kind, items, to, images)?, to)
         }
 
-        AstNode::ListItem(_, ast) if defr => NodeToHtmlResult::Single(
+        AstNode::WarningBlock((_, bet)) if defr => {
+            NodeToHtmlResult::Single(bet_to_html_string(bet, images)?, to)
+        }
+
+        AstNode::HorizRule if defr => NodeToHtmlResult::Single("<hr>".to_string(), to),
+
+        AstNode::Quote(children) if defr => NodeToHtmlResult::Single(
             format!(
-                "<ul><li>{}</li></ul>",
-                ast_to_html_string(ast, to)?.output(&to)
+                "<blockquote>{}</blockquote>",
+                ast_to_html_string(children, to, images)?.output(&to)
             ),
             to,
         ),
 
-        AstNode::WarningBlock((_, bet)) if defr => {
-            NodeToHtmlResult::Single(bet_to_html_string(bet)?, to)
+        AstNode::Center(children) if defr => NodeToHtmlResult::Single(
+            format!(
+                r#"<div class="center">{}</div>"#,
+                ast_to_html_string(children, to, images)?.output(&to)
+            ),
+            to,
+        ),
+
+        AstNode::Verse(lines) if defr => NodeToHtmlResult::Single(
+            format!(
+                r#"<p class="verse">{}</p>"#,
+                lines
+                    .iter()
+                    .map(|line| bet_to_html_string(line, images))
+                    .collect::<Result<Vec<_>>>()?
+                    .join("<br>\n")
+            ),
+            to,
+        ),
+
+        AstNode::Figure {
+            src,
+            caption,
+            attrs,
+        } if defr => {
+            let img = img_tag(src, attrs, images);
+            NodeToHtmlResult::Single(
+                match caption {
+                    Some(caption) => format!(
+                        "<figure>{img}<figcaption>{}</figcaption></figure>",
+                        bet_to_html_string(caption, images)?
+                    ),
+                    None => format!("<figure>{img}</figure>"),
+                },
+                to,
+            )
         }
 
-        AstNode::HorizRule if defr => NodeToHtmlResult::Single("<hr>".to_string(), to),
+        AstNode::Example(text) if defr => NodeToHtmlResult::Single(
+            format!(r#"<pre class="example">{}</pre>"#, escape_html(text)),
+            to,
+        ),
+
+        // Shown in the page's footnote section instead
+        AstNode::FootnoteDefinition { .. } if defr => NodeToHtmlResult::Single("".to_string(), to),
+
+        AstNode::Footnotes(footnotes) if defr => {
+            let mut buf = r#"<section class="footnotes"><ol>"#.to_string();
+            for footnote in footnotes {
+                let back_links = (1..=footnote.references)
+                    .map(|nth| {
+                        format!(
+                            r##"<a class="footnote-back" href="#{}">&#8617;</a>"##,
+                            footnote_ref_id(footnote.number, nth)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                buf.push_str(&format!(
+                    r#"<li id="fn-{}">{} {}</li>"#,
+                    footnote.number,
+                    bet_to_html_string(&footnote.definition, images)?,
+                    back_links
+                ));
+            }
+            buf.push_str("</ol></section>");
+            NodeToHtmlResult::Single(buf, to)
+        }
+
+        AstNode::Error { text, message } if defr => NodeToHtmlResult::Single(
+            format!(
+                r#"<pre class="parse-error" title="{}">{}</pre>"#,
+                escape_html(message),
+                escape_html(text)
+            ),
+            to,
+        ),
+
+        AstNode::Table {
+            header,
+            body,
+            alignments,
+        } if defr => NodeToHtmlResult::Single(
+            format!(
+                "<table>{thead}<tbody>{tbody}</tbody></table>",
+                thead = if header.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(
+                        "<thead>{}</thead>",
+                        table_rows_to_html_string(header, alignments, "th", images)?
+                    )
+                },
+                tbody = table_rows_to_html_string(body, alignments, "td", images)?
+            ),
+            to,
+        ),
 
         AstNode::SourceBlock { language, code } if defr => {
             let syntect_lang = match language {
@@ -155,55 +413,355 @@ fn ast_node_to_html_string(node: &BackrefAstNode, to: OutputTo) -> Result<NodeTo
                 to,
             )
         }
-        _ => bail!("unimplemented HTML generation for node: {:#?}", node.inner),
+        _ => bail!(
+            "{}: unimplemented HTML generation for node: {:#?}",
+            node.span,
+            node.inner
+        ),
     })
 }
 
+fn list_to_html_string(
+    kind: &ListKind,
+    items: &[BackrefAstNode],
+    to: OutputTo,
+    images: &ImageInfos,
+) -> Result<String> {
+    let (tag, attrs) = match (kind, items.first().map(|i| &i.inner)) {
+        (
+            ListKind::Ordered,
+            Some(AstNode::ListItem {
+                bullet: ListBullet::Ordered(start, _),
+                ..
+            }),
+        ) if *start != 1 => ("ol", format!(r#" start="{}""#, start)),
+        (ListKind::Ordered, _) => ("ol", "".to_string()),
+        (ListKind::Unordered, _) => ("ul", "".to_string()),
+        (ListKind::Description, _) => ("dl", "".to_string()),
+    };
+
+    let mut buf = format!("<{tag}{attrs}>");
+    for item in items {
+        let (checkbox, term, children) = match &item.inner {
+            AstNode::ListItem {
+                checkbox,
+                term,
+                children,
+                ..
+            } => (checkbox, term, children),
+            other => bail!("illegal node {:?} in list", other),
+        };
+        let children = ast_to_html_string(children, to, images)?.output(&to);
+
+        match (kind, term) {
+            (ListKind::Description, Some(term)) => buf.push_str(&format!(
+                "<dt>{}</dt><dd>{}</dd>",
+                bet_to_html_string(term, images)?,
+                children
+            )),
+            _ => buf.push_str(&match checkbox {
+                Some(Checkbox::On) => format!(
+                    r#"<li class="on"><input type="checkbox" checked disabled> {children}</li>"#
+                ),
+                Some(Checkbox::Off) => {
+                    format!(r#"<li class="off"><input type="checkbox" disabled> {children}</li>"#)
+                }
+                Some(Checkbox::Partial) => {
+                    format!(r#"<li class="trans"><input type="checkbox" disabled> {children}</li>"#)
+                }
+                None => format!("<li>{children}</li>"),
+            }),
+        }
+    }
+    buf.push_str(&format!("</{tag}>"));
+
+    Ok(buf)
+}
+
+fn table_rows_to_html_string(
+    rows: &[TableRow],
+    alignments: &[Option<ColumnAlignment>],
+    cell_tag: &str,
+    images: &ImageInfos,
+) -> Result<String> {
+    let mut buf = String::new();
+    for row in rows {
+        buf.push_str("<tr>");
+        for (col, cell) in row.iter().enumerate() {
+            let style = match alignments.get(col) {
+                Some(Some(ColumnAlignment::Left)) => r#" style="text-align: left""#,
+                Some(Some(ColumnAlignment::Right)) => r#" style="text-align: right""#,
+                Some(Some(ColumnAlignment::Center)) => r#" style="text-align: center""#,
+                Some(None) | None => "",
+            };
+            buf.push_str(&format!(
+                "<{cell_tag}{style}>{}</{cell_tag}>",
+                bet_to_html_string(cell, images)?
+            ));
+        }
+        buf.push_str("</tr>");
+    }
+    Ok(buf)
+}
+
+fn toc_entries_to_html_string(entries: &[TocEntry]) -> String {
+    if entries.is_empty() {
+        return "".to_string();
+    }
+    let mut buf = "<ul>".to_string();
+    for entry in entries {
+        buf.push_str(&format!(
+            r##"<li><a href="#{}">{}</a>{}</li>"##,
+            escape_html(&entry.anchor),
+            escape_html(&entry.title),
+            toc_entries_to_html_string(&entry.children)
+        ));
+    }
+    buf.push_str("</ul>");
+    buf
+}
+
+pub fn toc_to_html_string(entries: &[TocEntry]) -> String {
+    format!(
+        r#"<nav class="toc">{}</nav>"#,
+        toc_entries_to_html_string(entries)
+    )
+}
+
+/// The TOC a page asks for with `#+OPTIONS: toc:t` or `toc:N`, for templates to put wherever
+/// they like. `#+TOC:` is for a TOC in the text instead.
+pub fn page_toc_html(page_children: &[BackrefAstNode]) -> Option<String> {
+    let depth = match page_option(page_children, "toc")? {
+        "t" => None,
+        depth => Some(depth.parse().ok()?),
+    };
+    Some(toc_to_html_string(&toc_entries(page_children, depth)))
+}
+
+/// The html id of the `nth` reference to footnote `number`, for back-links.
+fn footnote_ref_id(number: usize, nth: usize) -> String {
+    match nth {
+        1 => format!("fnref-{number}"),
+        nth => format!("fnref-{number}-{nth}"),
+    }
+}
+
+/// Escape text so it can go anywhere in html, including quoted attribute values.
+pub fn escape_html(s: &str) -> String {
+    let mut buf = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&#39;"),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
 // block expr tree
-#[tracing::instrument]
-pub fn bet_to_html_string(nodes: &BlockExprTree) -> Result<String> {
+#[tracing::instrument(skip(images))]
+pub fn bet_to_html_string(nodes: &BlockExprTree, images: &ImageInfos) -> Result<String> {
     let mut buf = String::with_capacity(4096);
     for node in nodes {
-        buf.push_str(&block_expr_to_html_string(node)?);
+        buf.push_str(&block_expr_to_html_string(node, images)?);
     }
     Ok(buf)
 }
 
-fn block_expr_to_html_string(node: &BlockExprNode) -> Result<String> {
+fn block_expr_to_html_string(node: &Spanned<BlockExprNode>, images: &ImageInfos) -> Result<String> {
     let unreachable = Err(anyhow!(
-        "illegal node {:?}; parser pass should have eliminated this",
-        node
+        "{}: illegal node {:?}; parser pass should have eliminated this",
+        node.span,
+        node.inner
     ));
-    match node {
-        BlockExprNode::Bold(bet) => Ok(format!("<strong>{}</strong>", bet_to_html_string(bet)?)),
-        BlockExprNode::Char(c) => Ok(c.to_string()),
+    match &node.inner {
+        BlockExprNode::Bold(bet) => Ok(format!(
+            "<strong>{}</strong>",
+            bet_to_html_string(bet, images)?
+        )),
+        BlockExprNode::Char(c) => Ok(escape_html(c.encode_utf8(&mut [0; 4]))),
         BlockExprNode::Linespace | BlockExprNode::NonbreakingSpace(_) => unreachable,
-        BlockExprNode::Italic(bet) => Ok(format!("<em>{}</em>", bet_to_html_string(bet)?)),
+        BlockExprNode::Italic(bet) => Ok(format!("<em>{}</em>", bet_to_html_string(bet, images)?)),
         BlockExprNode::Underline(bet) => Ok(format!(
             "<span class=\"underline\">{}</span>",
-            bet_to_html_string(bet)?
+            bet_to_html_string(bet, images)?
         )),
-        BlockExprNode::Strikethrough(bet) => Ok(format!("<del>{}</del>", bet_to_html_string(bet)?)),
-        BlockExprNode::Code(verbatim) => Ok(format!(r#"<span class="code">{}</span>"#, verbatim)),
-        BlockExprNode::Link(url, maybe_bet) => Ok(format!(
-            r#"<a href="{}">{}</a>"#,
-            match url {
-                LinkTarget::External(u) => u,
-                LinkTarget::Heading { title: _t } => "TODO",
-            },
-            match maybe_bet {
-                Some(bet) => bet_to_html_string(bet)?,
-                None => panic!("unimplemented"),
-            }
+        BlockExprNode::Strikethrough(bet) => {
+            Ok(format!("<del>{}</del>", bet_to_html_string(bet, images)?))
+        }
+        BlockExprNode::Code(verbatim) => Ok(format!(
+            r#"<span class="code">{}</span>"#,
+            escape_html(verbatim)
+        )),
+        BlockExprNode::Math { display, latex } => Ok(latex_to_mathml(latex, *display)),
+        BlockExprNode::Export { backend, content } if backend == "html" => Ok(content.clone()),
+        BlockExprNode::Export { .. } => Ok("".to_string()),
+        BlockExprNode::Link(LinkTarget::External(url), None) if is_image_url(url) => {
+            Ok(img_tag(url, &[], images))
+        }
+        BlockExprNode::Link(url, maybe_bet) => {
+            let href = match url {
+                LinkTarget::External(u) => safe_url(u),
+                LinkTarget::Internal { page, anchor } => match anchor {
+                    Some(anchor) => format!("/{}#{}", page, anchor),
+                    None => format!("/{}", page),
+                },
+                // pass3 could not find it, see the link checker
+                LinkTarget::Heading { title } => format!("#{}", slugify(title)),
+            };
+            Ok(format!(
+                r#"<a href="{}">{}</a>"#,
+                escape_html(&href),
+                match (maybe_bet, url) {
+                    (Some(bet), _) => bet_to_html_string(bet, images)?,
+                    // Without a description the link is its own text.
+                    (None, LinkTarget::External(u)) => escape_html(u),
+                    (None, LinkTarget::Heading { title }) => escape_html(title),
+                    (None, LinkTarget::Internal { .. }) => escape_html(&href),
+                }
+            ))
+        }
+        BlockExprNode::FootnoteRef {
+            number: Some((number, nth)),
+            ..
+        } => Ok(format!(
+            r##"<sup class="footnote-ref"><a id="{}" href="#fn-{number}">{number}</a></sup>"##,
+            footnote_ref_id(*number, *nth)
         )),
+        // Undefined, see the footnote checker
+        BlockExprNode::FootnoteRef {
+            label: Some(label),
+            number: None,
+            ..
+        } => Ok(escape_html(&format!("[fn:{label}]"))),
+        BlockExprNode::FootnoteRef { .. } => Ok("".to_string()),
         BlockExprNode::HeaderRouting(_hr) => unreachable,
         BlockExprNode::Warning(bet) => Ok(format!(
             r#"<div class="warning">{}</div>"#,
-            bet_to_html_string(bet)?
+            bet_to_html_string(bet, images)?
         )),
         BlockExprNode::FloatToggle(bet) => Ok(format!(
             r#"<span class="float">{}</span>"#,
-            bet_to_html_string(bet)?
+            bet_to_html_string(bet, images)?
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_n_pass;
+
+    fn render(input: &str) -> String {
+        render_with(input, &ImageInfos::new())
+    }
+
+    fn render_with(input: &str, images: &ImageInfos) -> String {
+        ast_to_html_string(&parse_n_pass(input).unwrap(), OutputTo::Main, images)
+            .unwrap()
+            .main
+    }
+
+    fn cat() -> ImageInfos {
+        ImageInfos::from([(
+            "/static/cat.png".to_string(),
+            ImageInfo {
+                width: 1000,
+                height: 500,
+                variants: vec![("/image-cache/cat-480w.png".to_string(), 480)],
+            },
+        )])
+    }
+
+    #[test]
+    fn table_html() {
+        assert_eq!(
+            render("| a | b |\n|---+---|\n| <r> |\n| 1 | /2/ |\n"),
+            concat!(
+                "<table><thead><tr><th style=\"text-align: right\">a</th><th>b</th></tr></thead>",
+                "<tbody><tr><td style=\"text-align: right\">1</td><td><em>2</em></td></tr></tbody></table>"
+            )
+        );
+    }
+
+    #[test]
+    fn list_html() {
+        assert_eq!(
+            render("3. [X] a\n   - [ ] b\n4. c\n- term :: def\n"),
+            concat!(
+                "<ol start=\"3\"><li class=\"on\"><input type=\"checkbox\" checked disabled> <p>a</p>",
+                "<ul><li class=\"off\"><input type=\"checkbox\" disabled> <p>b</p></li></ul></li>",
+                "<li><p>c</p></li></ol>",
+                "<dl><dt>term</dt><dd><p>def</p></dd></dl>"
+            )
+        );
+    }
+
+    #[test]
+    fn nav_is_only_links() {
+        let ast = parse_n_pass(concat!(
+            "* home :index:\n** :@nav:\n- [[/a][a]]\n\n",
+            "| x |\n\n#+TOC: headlines 1\n\n-----\n\n#+BEGIN_SRC sh\nls\n#+END_SRC\n",
+        ))
+        .unwrap();
+        // Like the site does, from the children of the page.
+        let page = ast[0].inner.children().unwrap();
+        let nav = ast_to_html_string(page, OutputTo::Main, &ImageInfos::new())
+            .unwrap()
+            .nav;
+        assert_eq!(nav, vec![r#"<a href="/a">a</a>"#]);
+    }
+
+    #[test]
+    fn img_without_info() {
+        assert_eq!(
+            render("[[/static/dog.png]]\n"),
+            r#"<p><img src="/static/dog.png" alt="" loading="lazy"></p>"#
+        );
+    }
+
+    #[test]
+    fn img_with_info() {
+        assert_eq!(
+            render_with("[[/static/cat.png]]\n", &cat()),
+            concat!(
+                r#"<p><img src="/static/cat.png" alt="" width="1000" height="500" "#,
+                r#"srcset="/image-cache/cat-480w.png 480w, /static/cat.png 1000w" "#,
+                r#"sizes="(max-width: 1000px) 100vw, 1000px" loading="lazy"></p>"#
+            )
+        );
+    }
+
+    #[test]
+    fn img_attrs_win() {
+        let html = render_with(
+            "#+ATTR_HTML: :loading eager :srcset /static/cat@2x.png 2x :width 500\n[[/static/cat.png]]\n",
+            &cat(),
+        );
+        assert_eq!(
+            html,
+            concat!(
+                r#"<figure><img src="/static/cat.png" loading="eager" "#,
+                r#"srcset="/static/cat@2x.png 2x" width="500" alt="" height="250"></figure>"#
+            )
+        );
+    }
+
+    #[test]
+    fn image_srcs_finds_every_image() {
+        let ast = parse_n_pass(
+            "* page :page:\n[[/static/a.png]] [[/static/a.png][not an image]]\n- *[[/static/b.jpg]]*\n\n#+CAPTION: c\n[[/static/c.svg]]\n",
+        )
+        .unwrap();
+        let mut srcs = image_srcs(&ast).into_iter().collect::<Vec<_>>();
+        srcs.sort();
+        assert_eq!(
+            srcs,
+            vec!["/static/a.png", "/static/b.jpg", "/static/c.svg"]
+        );
+    }
+}
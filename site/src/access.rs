@@ -0,0 +1,141 @@
+//! Who is looking? Viewers prove their groups with a token from `access.tokens` in the content
+//! folder, passed as `?token=`, a `token` cookie or an `Authorization: Bearer` header.
+
+use anyhow::Result;
+use hyper::{
+    header::{AUTHORIZATION, COOKIE},
+    HeaderMap, Uri,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+};
+
+use crate::{serve::CONTENT_DIR, ARGS};
+
+/// What a request is allowed to see.
+#[derive(Debug, Default)]
+pub struct Viewer {
+    pub groups: HashSet<String>,
+    /// Set when a valid token came in through the query, so the viewer can keep browsing without
+    /// it. The request should then go to [`without_token`] so the token leaves the address bar.
+    pub set_cookie: Option<String>,
+}
+
+/// `access.tokens` has one `token group group…` per line. `#` starts a comment.
+/// Without the file, nobody gets into restricted pages.
+fn load_tokens() -> Result<HashMap<String, HashSet<String>>> {
+    let file = match CONTENT_DIR.read_to_string("access.tokens") {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(file
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let token = words.next()?;
+            Some((token.to_string(), words.map(str::to_string).collect()))
+        })
+        .collect())
+}
+
+pub fn viewer(uri: &Uri, headers: &HeaderMap) -> Result<Viewer> {
+    let query_token = uri.query().and_then(|q| {
+        q.split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .and_then(percent_decode)
+    });
+    let header_token = || {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|v| v.trim().to_string())
+    };
+    let cookie_token = || {
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .find_map(|pair| pair.trim().strip_prefix("token="))
+            .and_then(percent_decode)
+    };
+
+    let token = match query_token
+        .clone()
+        .or_else(header_token)
+        .or_else(cookie_token)
+    {
+        Some(token) => token,
+        None => return Ok(Viewer::default()),
+    };
+
+    match load_tokens()?.remove(&token) {
+        Some(groups) => Ok(Viewer {
+            groups,
+            set_cookie: query_token.map(|token| {
+                // Browsers drop `Secure` cookies over plain http, which is what dev runs on.
+                let secure = match &ARGS.base_url {
+                    Some(base_url) if base_url.starts_with("https://") => "; Secure",
+                    _ => "",
+                };
+                format!(
+                    "token={}; Path=/; HttpOnly; SameSite=Lax{secure}",
+                    percent_encode(&token)
+                )
+            }),
+        }),
+        None => Ok(Viewer::default()),
+    }
+}
+
+/// Where to send a request that brought its token in the query: the same page and query, minus
+/// the token.
+pub fn without_token(uri: &Uri) -> String {
+    let query = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("token="))
+        .collect::<Vec<_>>()
+        .join("&");
+    if query.is_empty() {
+        uri.path().to_string()
+    } else {
+        format!("{}?{query}", uri.path())
+    }
+}
+
+/// Tokens are arbitrary words, so they come in percent-encoded. `None` for broken escapes and
+/// anything that isn't UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The other way around, for the cookie. Only leaves in what is safe in a cookie value.
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
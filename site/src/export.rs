@@ -0,0 +1,145 @@
+//! `site build`: renders every page to plain files so the site can live on any static host.
+
+use anyhow::{anyhow, bail, Result};
+use hyper::Uri;
+use orgish::{
+    access::strip_restricted,
+    parse::{page_redirect, AstNode, Route},
+    treewalk::escape_html,
+};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+use tracing::{info, warn};
+
+use crate::{
+    feed::render_feed,
+    images::image_infos,
+    serve::{is_reserved, load_ast, render_page, OutputFormat, INDEX_URI},
+    sitemap::{render_robots, render_sitemap},
+    ARGS,
+};
+
+/// Only what anonymous viewers may see is exported, since there is nobody to check tokens.
+pub fn build_site(out_dir: &Path) -> Result<()> {
+    let ast = strip_restricted(&load_ast()?, &HashSet::new());
+    let images = image_infos(&ast);
+
+    for node in &ast {
+        if let AstNode::Heading {
+            routing: Some(Route::Page(pg)),
+            children,
+            ..
+        } = &node.inner
+        {
+            let uri = Uri::from_str(&format!("/{pg}"))?;
+            let html = match page_redirect(children) {
+                Some(target) => redirect_stub(target),
+                None => render_page(&ast, &uri, OutputFormat::Html, &images)?
+                    .ok_or_else(|| anyhow!("page {pg} disappeared while rendering"))?,
+            };
+            // Static hosts look for these two at the root.
+            let dest = if is_reserved(pg) {
+                out_dir.join(format!("{pg}.html"))
+            } else {
+                out_dir.join(page_file(pg)?)
+            };
+            write_file(&dest, &html)?;
+
+            // `/` is served as /index, and its req_path says so too.
+            if uri == *INDEX_URI {
+                write_file(&out_dir.join("index.html"), &html)?;
+            }
+        }
+    }
+
+    let base_url = ARGS
+        .base_url
+        .as_deref()
+        .map(|url| url.trim_end_matches('/'));
+    match base_url {
+        Some(base_url) => {
+            write_file(
+                &out_dir.join("feed.xml"),
+                &render_feed(&ast, base_url, &images)?,
+            )?;
+            write_file(
+                &out_dir.join("sitemap.xml"),
+                &render_sitemap(&ast, base_url)?,
+            )?;
+        }
+        None => warn!("not writing feed.xml and sitemap.xml without --base-url"),
+    }
+    write_file(&out_dir.join("robots.txt"), &render_robots(base_url)?)?;
+
+    copy_dir(&ARGS.static_path, &out_dir.join("static"))?;
+    // Looking the images up filled it with everything the pages use. It is only made once there
+    // is an image from the static folder to look up, so it may not be there at all.
+    if let Some(image_cache) = ARGS.image_cache.as_ref().filter(|path| path.exists()) {
+        copy_dir(image_cache, &out_dir.join("image-cache"))?;
+    }
+
+    Ok(())
+}
+
+/// Static hosts can't send a real redirect, so moved pages get a page that redirects by itself.
+fn redirect_stub(target: &str) -> String {
+    let target = escape_html(target);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta http-equiv="refresh" content="0; url={target}">
+  <link rel="canonical" href="{target}">
+  <title>Moved</title>
+</head>
+<body>
+  This page has moved <a href="{target}">here</a>.
+</body>
+</html>
+"#
+    )
+}
+
+/// Where a page ends up in the output folder. Routes that look like a file name (moved pages such
+/// as `:tailscale-coredns.html:`) are written at exactly that path, so old links keep landing on
+/// the stub that points to the new location. Everything else becomes `<page>/index.html`.
+fn page_file(pg: &str) -> Result<PathBuf> {
+    let path = Path::new(pg);
+    if pg.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        bail!("refusing to write page {pg:?} outside of the output folder");
+    }
+
+    if path.extension().is_some() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(path.join("index.html"))
+    }
+}
+
+fn write_file(dest: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    info!("writing {}", dest.display());
+    fs::write(dest, contents)?;
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
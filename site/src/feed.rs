@@ -0,0 +1,154 @@
+//! `/feed.xml`, an Atom feed of every page with a `#+DATE:`. `#+OPTIONS: feed:nil` leaves a page
+//! out, and so does `draft:t`.
+
+use anyhow::Result;
+use orgish::{
+    access::strip_restricted,
+    parse::{
+        is_draft, page_option, page_redirect, stringify_bet, AstNode, Directive, OutputTo,
+        PassedSyntaxTree, Route,
+    },
+    treewalk::{ast_to_html_string, escape_html, ImageInfos},
+};
+use std::{collections::HashSet, fmt::Write};
+use tracing::warn;
+
+use crate::serve::INDEX_URI;
+
+struct Entry {
+    page: String,
+    title: String,
+    updated: String,
+    content: String,
+}
+
+/// `2022-05-01`, or an Org timestamp like `<2022-05-01 Sun 13:37>`, as an RFC 3339 date in UTC.
+pub fn atom_date(date: &str) -> Option<String> {
+    let mut words = date
+        .trim_matches(|c: char| c.is_whitespace() || "<>[]".contains(c))
+        .split_whitespace();
+    let day = words.next()?;
+    let time = words.find(|w| w.contains(':')).unwrap_or("00:00");
+
+    let is_digits = |s: &str, len: usize| s.len() == len && s.chars().all(|c| c.is_ascii_digit());
+    match (
+        &day.split('-').collect::<Vec<_>>()[..],
+        &time.split(':').collect::<Vec<_>>()[..],
+    ) {
+        (&[y, m, d], &[h, min])
+            if is_digits(y, 4)
+                && is_digits(m, 2)
+                && is_digits(d, 2)
+                && is_digits(h, 2)
+                && is_digits(min, 2) =>
+        {
+            Some(format!("{day}T{time}:00Z"))
+        }
+        _ => None,
+    }
+}
+
+/// Only what anonymous viewers may see goes into the feed, feed readers don't send tokens. Moved
+/// pages are left out like drafts, their stub has nothing to read.
+/// `base_url` has no trailing slash and is where the site is hosted, since Atom ids and links
+/// have to be absolute.
+pub fn render_feed(ast: &PassedSyntaxTree, base_url: &str, images: &ImageInfos) -> Result<String> {
+    let ast = strip_restricted(ast, &HashSet::new());
+    let index_page = &INDEX_URI.path()[1..];
+
+    let mut feed_title = base_url.to_string();
+    let mut entries = vec![];
+    for node in &ast {
+        let (pg, title, children) = match &node.inner {
+            AstNode::Heading {
+                routing: Some(Route::Page(pg)),
+                title,
+                children,
+                ..
+            } => (pg, title, children),
+            _ => continue,
+        };
+        if pg == index_page {
+            feed_title = stringify_bet(title)?;
+        }
+
+        let date = children.iter().find_map(|child| match &child.inner {
+            AstNode::Directive(Directive::Date(date)) => Some(date),
+            _ => None,
+        });
+        let date = match date {
+            Some(date)
+                if page_option(children, "feed") != Some("nil")
+                    && !is_draft(children)
+                    && page_redirect(children).is_none() =>
+            {
+                date
+            }
+            _ => continue,
+        };
+        let updated = match atom_date(date) {
+            Some(updated) => updated,
+            None => {
+                warn!("page {pg} has a #+DATE: that isn't YYYY-MM-DD: {date:?}");
+                continue;
+            }
+        };
+
+        entries.push(Entry {
+            page: pg.clone(),
+            title: stringify_bet(title)?,
+            updated,
+            content: ast_to_html_string(children, OutputTo::Main, images)?.main,
+        });
+    }
+    // Newest first
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated).then(a.page.cmp(&b.page)));
+
+    let mut buf = String::new();
+    writeln!(buf, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    // xml:base makes the site-relative links in the content work.
+    writeln!(
+        buf,
+        r#"<feed xmlns="http://www.w3.org/2005/Atom" xml:base="{}/">"#,
+        escape_html(base_url)
+    )?;
+    writeln!(buf, "<title>{}</title>", escape_html(&feed_title))?;
+    writeln!(
+        buf,
+        "<author><name>{}</name></author>",
+        escape_html(&feed_title)
+    )?;
+    writeln!(buf, r#"<link href="{}/"/>"#, escape_html(base_url))?;
+    writeln!(
+        buf,
+        r#"<link rel="self" href="{}/feed.xml"/>"#,
+        escape_html(base_url)
+    )?;
+    writeln!(buf, "<id>{}/</id>", escape_html(base_url))?;
+    writeln!(
+        buf,
+        "<updated>{}</updated>",
+        entries
+            .first()
+            .map_or("1970-01-01T00:00:00Z", |entry| &entry.updated)
+    )?;
+
+    for entry in &entries {
+        // The page URL doubles as the id, so it only changes when the page moves.
+        let url = escape_html(&format!("{base_url}/{}", entry.page));
+        writeln!(buf, "<entry>")?;
+        writeln!(buf, "<title>{}</title>", escape_html(&entry.title))?;
+        writeln!(buf, r#"<link href="{url}"/>"#)?;
+        writeln!(buf, "<id>{url}</id>")?;
+        writeln!(buf, "<updated>{}</updated>", entry.updated)?;
+        writeln!(
+            buf,
+            r#"<content type="html">{}</content>"#,
+            escape_html(&entry.content)
+        )?;
+        writeln!(buf, "</entry>")?;
+    }
+    writeln!(buf, "</feed>")?;
+
+    Ok(buf)
+}
@@ -0,0 +1,145 @@
+//! Sizes of the images in the static folder, and smaller copies of them for `srcset`, so pages
+//! don't jump around while images load and small screens don't get the full-size ones.
+//! Copies are only made with `--image-cache`, and made again when the original changes.
+
+use anyhow::{Context, Result};
+use cap_std::{ambient_authority, fs::Dir};
+use image::{imageops::FilterType, io::Reader, ImageFormat};
+use once_cell::sync::OnceCell;
+use orgish::{
+    parse::BackrefAstNode,
+    treewalk::{image_srcs, ImageInfo, ImageInfos},
+};
+use std::{
+    fs,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+use tracing::{info, warn};
+
+use crate::{serve::STATIC_DIR, ARGS};
+
+/// Widths of the smaller copies. Only the ones narrower than the original are made.
+const VARIANT_WIDTHS: [u32; 3] = [480, 960, 1440];
+
+/// Only set with `--image-cache`, by [`open_image_cache`].
+static IMAGE_CACHE_DIR: OnceCell<Dir> = OnceCell::new();
+
+/// Makes and opens the `--image-cache` folder. Call it before anything looks at images, so a bad
+/// path stops the server from starting instead of breaking requests.
+pub fn open_image_cache() -> Result<()> {
+    if let Some(path) = &ARGS.image_cache {
+        fs::create_dir_all(path)
+            .with_context(|| format!("couldn't create the image cache {}", path.display()))?;
+        let dir = Dir::open_ambient_dir(path, ambient_authority())
+            .with_context(|| format!("couldn't open the image cache {}", path.display()))?;
+        // Only fails if it was opened already, and then it is the same folder.
+        let _ = IMAGE_CACHE_DIR.set(dir);
+    }
+    Ok(())
+}
+
+pub fn image_cache_dir() -> Option<&'static Dir> {
+    IMAGE_CACHE_DIR.get()
+}
+
+/// Looks up every image in `ast` for rendering. This reads the images and makes the smaller
+/// copies that are missing, which can take a while, so keep it off the async workers.
+pub fn image_infos(ast: &[BackrefAstNode]) -> ImageInfos {
+    image_srcs(ast)
+        .into_iter()
+        .filter_map(|src| Some((src.clone(), image_info(&src)?)))
+        .collect()
+}
+
+/// Only images in the static folder are ours, and only the formats the `image` crate reads, so
+/// no SVGs.
+fn image_info(src: &str) -> Option<ImageInfo> {
+    let path = src.strip_prefix("/static/")?;
+    // `?v=2` and friends are for cache busting, not part of the file name.
+    let path = path.split(|c| c == '?' || c == '#').next()?;
+    let format = ImageFormat::from_path(path).ok()?;
+    if !matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+    ) {
+        return None;
+    }
+
+    match read_image_info(path, format) {
+        Ok(info) => Some(info),
+        Err(err) => {
+            warn!("couldn't look at image {src}: {err}");
+            None
+        }
+    }
+}
+
+fn read_image_info(path: &str, format: ImageFormat) -> Result<ImageInfo> {
+    let file = STATIC_DIR.open(path)?;
+    let modified = file.metadata()?.modified()?;
+    let (width, height) =
+        Reader::with_format(BufReader::new(file.into_std()), format).into_dimensions()?;
+
+    let cache = match image_cache_dir() {
+        // Animated GIFs would stop moving, and the `image` crate can't write WebP.
+        Some(cache) if matches!(format, ImageFormat::Png | ImageFormat::Jpeg) => cache,
+        _ => {
+            return Ok(ImageInfo {
+                width,
+                height,
+                variants: vec![],
+            })
+        }
+    };
+
+    let variants = VARIANT_WIDTHS
+        .into_iter()
+        .filter(|variant_width| *variant_width < width)
+        .map(|variant_width| (variant_name(path, variant_width), variant_width))
+        .collect::<Vec<_>>();
+    let stale = variants
+        .iter()
+        .filter(|(name, _)| {
+            cache
+                .metadata(name)
+                .and_then(|meta| meta.modified())
+                .map_or(true, |variant_modified| variant_modified < modified)
+        })
+        .collect::<Vec<_>>();
+    if !stale.is_empty() {
+        let original =
+            Reader::with_format(BufReader::new(STATIC_DIR.open(path)?.into_std()), format)
+                .decode()?;
+        for (name, variant_width) in stale {
+            info!("making {name}");
+            let variant = original.resize(*variant_width, u32::MAX, FilterType::Triangle);
+            if let Some(parent) = Path::new(name).parent() {
+                cache.create_dir_all(parent)?;
+            }
+            // Written next to it first, so nobody gets served half an image.
+            let tmp = format!("{name}.tmp");
+            let mut out = BufWriter::new(cache.create(&tmp)?.into_std());
+            variant.write_to(&mut out, format)?;
+            out.flush()?;
+            cache.rename(&tmp, cache, name)?;
+        }
+    }
+
+    Ok(ImageInfo {
+        width,
+        height,
+        variants: variants
+            .into_iter()
+            .map(|(name, variant_width)| (format!("/image-cache/{name}"), variant_width))
+            .collect(),
+    })
+}
+
+/// `photos/cat.jpg` at 480 pixels wide is `photos/cat-480w.jpg`
+fn variant_name(path: &str, width: u32) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, ext)) => format!("{stem}-{width}w.{ext}"),
+        None => format!("{path}-{width}w"),
+    }
+}
@@ -1,5 +1,10 @@
-use axum::{error_handling::HandleError, Router};
-use clap::Parser;
+use axum::{
+    error_handling::HandleError,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use clap::{Parser, Subcommand};
 use hyper::StatusCode;
 use lazy_static::lazy_static;
 use std::{net::SocketAddr, path::PathBuf};
@@ -9,7 +14,14 @@ use tracing_subscriber::{prelude::*, EnvFilter};
 
 use crate::serve::fill_caches;
 
+pub mod access;
+pub mod export;
+pub mod feed;
+pub mod images;
+pub mod reload;
 pub mod serve;
+pub mod sitemap;
+pub mod static_file;
 
 /// Frontend for orgish to serve website
 #[derive(Parser, Debug)]
@@ -29,9 +41,36 @@ pub struct Args {
     /// Path to the static folder
     static_path: PathBuf,
 
-    /// Whether to cache index.org. This saves around ~20ms per request.
+    /// Whether to cache the parsed content folder. This saves around ~20ms per request. The cache is refreshed
+    /// whenever the content folder changes.
     #[clap(short, long)]
     cache_org: bool,
+
+    /// Make open tabs reload when the content or static folders change
+    #[clap(short, long)]
+    dev: bool,
+
+    /// Where the site is hosted, e.g. https://ckie.dev. The feed needs absolute links, the server
+    /// falls back to the Host header without this.
+    #[clap(long)]
+    base_url: Option<String>,
+
+    /// Where to keep smaller copies of the images in the static folder, for small screens.
+    /// Keep it outside of the static folder. Without this, images are only sent as they are.
+    #[clap(long)]
+    image_cache: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Render every page into a folder for plain file hosting instead of serving
+    Build {
+        /// Where to write the site to
+        out_dir: PathBuf,
+    },
 }
 
 lazy_static! {
@@ -49,6 +88,12 @@ async fn main() -> anyhow::Result<()> {
             .with(tracing_subscriber::fmt::layer()) // set RUST_LOG=debug
     })?;
 
+    images::open_image_cache()?;
+
+    if let Some(Command::Build { out_dir }) = &ARGS.command {
+        return export::build_site(out_dir);
+    }
+
     if !ARGS.cache_org {
         warn!("--cache-org is not set! expect degraded performance");
     }
@@ -56,7 +101,15 @@ async fn main() -> anyhow::Result<()> {
     // Warm the server up.
     serve::fill_caches();
 
-    let app = Router::new().fallback(HandleError::new(
+    if ARGS.cache_org || ARGS.dev {
+        reload::spawn_watcher()?;
+    }
+
+    let mut app = Router::new();
+    if ARGS.dev {
+        app = app.route("/_reload", get(reload::reload_events));
+    }
+    let app = app.fallback(HandleError::new(
         service_fn(serve::fallback_handler),
         handle_anyhow_error,
     ));
@@ -77,10 +130,18 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-pub async fn handle_anyhow_error(err: anyhow::Error) -> (StatusCode, String) {
+pub async fn handle_anyhow_error(err: anyhow::Error) -> Response {
     error!("error while serving request: {}", err);
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        "500 oopsie doopsie".to_string(),
-    )
+    let page = serve::render_error_page(&err).unwrap_or_else(|page_err| {
+        error!("the error page broke too: {}", page_err);
+        None
+    });
+    match page {
+        Some(html) => (StatusCode::INTERNAL_SERVER_ERROR, Html(html)).into_response(),
+        None => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "500 oopsie doopsie".to_string(),
+        )
+            .into_response(),
+    }
 }
@@ -0,0 +1,58 @@
+//! Watches the content and static folders, keeps the cached AST fresh and tells open tabs to
+//! reload in dev mode.
+
+use anyhow::Result;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use lazy_static::lazy_static;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::{convert::Infallible, sync::mpsc, thread, time::Duration};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tracing::{debug, warn};
+
+use crate::{serve::reload_ast, ARGS};
+
+/// Injected before `</body>` in dev mode.
+pub const RELOAD_SCRIPT: &str =
+    r#"<script>new EventSource("/_reload").onmessage = () => location.reload();</script>"#;
+
+lazy_static! {
+    static ref RELOAD_TX: broadcast::Sender<()> = broadcast::channel(16).0;
+}
+
+pub fn spawn_watcher() -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = watcher(tx, Duration::from_millis(200))?;
+    watcher.watch(&ARGS.content_path, RecursiveMode::Recursive)?;
+    watcher.watch(&ARGS.static_path, RecursiveMode::Recursive)?;
+
+    thread::spawn(move || {
+        // Dropping the watcher stops the events.
+        let _watcher = watcher;
+        for event in rx {
+            match event {
+                DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => continue,
+                DebouncedEvent::Error(err, path) => {
+                    warn!("file watcher error at {path:?}: {err}");
+                    continue;
+                }
+                event => debug!("{event:?}"),
+            }
+
+            if ARGS.cache_org && !reload_ast() {
+                continue;
+            }
+            // An error only means no tabs are listening right now.
+            let _ = RELOAD_TX.send(());
+        }
+    });
+
+    Ok(())
+}
+
+/// `/_reload`, an SSE stream that says "reload" every time something changed.
+pub async fn reload_events() -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(RELOAD_TX.subscribe())
+        .filter_map(|msg| msg.ok().map(|()| Ok(Event::default().data("reload"))));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
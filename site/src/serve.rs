@@ -1,51 +1,96 @@
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use axum::{
     extract::Query,
-    http::HeaderValue,
     response::{Html, IntoResponse, Response},
 };
 use cap_std::{ambient_authority, fs::Dir};
-use hyper::{header::CONTENT_TYPE, Request, StatusCode, Uri};
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE, HOST, LOCATION, SET_COOKIE},
+    HeaderMap, Request, StatusCode, Uri,
+};
 use include_dir::{include_dir, Dir as CompDir};
-use lazy_static::{__Deref, lazy_static};
+use lazy_static::lazy_static;
 use liquid::{object, ParserBuilder};
 use orgish::{
+    access::strip_restricted,
+    check::{check_files, Diagnostic},
+    json::to_json,
     parse::{
-        parse_n_pass, stringify_bet, AstNode, BackrefAstNode, OutputTo, PassedSyntaxTree, Route,
+        page_redirect, parse_n_pass_pages, stringify_bet, AstNode, BackrefAstNode, OutputTo,
+        PageFile, ParsedPages, PassedSyntaxTree, Route, INDEX_PAGE,
     },
-    treewalk::{ast_to_html_string, bet_to_html_string},
+    treewalk::{ast_to_html_string, bet_to_html_string, escape_html, page_toc_html, ImageInfos},
 };
 use std::{str::FromStr, intrinsics::transmute};
-use std::{fmt::Write, mem::MaybeUninit};
+use std::fmt::Write;
+use std::{
+    collections::HashSet,
+    iter, slice,
+    sync::{Arc, RwLock},
+};
+use tracing::{error, info, warn};
 
-use crate::ARGS;
+use crate::{
+    access::{viewer, without_token},
+    feed::render_feed,
+    images::{image_cache_dir, image_infos},
+    reload::RELOAD_SCRIPT,
+    sitemap::{render_robots, render_sitemap},
+    static_file::serve_static_file,
+    ARGS,
+};
 
 lazy_static! {
-    static ref INDEX_URI: Uri = Uri::from_str("/index").unwrap();
-    static ref CONTENT_DIR: Dir =
+    pub static ref INDEX_URI: Uri = Uri::from_str(&format!("/{INDEX_PAGE}")).unwrap();
+    /// The `:404:` page, shown for anything that isn't there.
+    pub static ref NOT_FOUND_URI: Uri = Uri::from_str("/404").unwrap();
+    /// The `:500:` page, shown when something breaks while serving.
+    pub static ref ERROR_URI: Uri = Uri::from_str("/500").unwrap();
+    pub static ref CONTENT_DIR: Dir =
         Dir::open_ambient_dir(&ARGS.content_path, ambient_authority()).unwrap();
-    static ref STATIC_DIR: Dir =
+    pub static ref STATIC_DIR: Dir =
         Dir::open_ambient_dir(&ARGS.static_path, ambient_authority()).unwrap();
-    // Only used with --cache-org
-    static ref AST: Result<Vec<BackrefAstNode>> = {
-        let org_file = CONTENT_DIR.read_to_string("index.org")?;
-        parse_n_pass(&org_file)
-    };
+    // Only used with --cache-org, swapped out by the watcher when the content folder changes
+    static ref AST: RwLock<Arc<Vec<BackrefAstNode>>> = RwLock::new(Arc::new(
+        load_ast().unwrap_or_else(|err| panic!("Cached AST parse failed: {err}"))
+    ));
+    /// The images of [`AST`], looked up along with it so requests don't have to.
+    static ref IMAGES: RwLock<Arc<ImageInfos>> =
+        RwLock::new(Arc::new(image_infos(&AST.read().unwrap())));
 }
 
 pub fn fill_caches() {
     use lazy_static::initialize;
     initialize(&AST);
+    initialize(&IMAGES);
     initialize(&CONTENT_DIR);
     initialize(&STATIC_DIR);
-    if let Err(err) = AST.deref() {
-        panic!("Cached AST parse failed: {err}");
+}
+
+/// Re-parses the content folder into the cached AST. A failed parse leaves the last good tree in place.
+/// Returns whether the cache was swapped.
+pub fn reload_ast() -> bool {
+    match load_ast() {
+        Ok(ast) => {
+            let images = image_infos(&ast);
+            *AST.write().unwrap() = Arc::new(ast);
+            *IMAGES.write().unwrap() = Arc::new(images);
+            info!("reloaded the content folder");
+            true
+        }
+        Err(err) => {
+            error!("the content folder failed to parse, still serving the last good tree: {err}");
+            false
+        }
     }
 }
 
-enum OutputFormat {
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
     Html,
     Ast,
+    /// See orgish's README for the schema
+    Json,
 }
 
 #[tracing::instrument(skip_all)]
@@ -57,91 +102,364 @@ pub async fn fallback_handler<B>(req: Request<B>) -> Result<Response> {
     };
 
     // Syntax !@*(#&@!(*#&))11
-    let output_format = if let Some(q) = uri.query() {
-        if q.contains("ast") {
-            OutputFormat::Ast
-        } else {
-            OutputFormat::Html
-        }
+    let has_param = |param| {
+        uri.query()
+            .map_or(false, |q| q.split('&').any(|pair| pair == param))
+    };
+    let output_format = if has_param("format=json") {
+        OutputFormat::Json
+    } else if has_param("ast") {
+        OutputFormat::Ast
     } else {
         OutputFormat::Html
     };
 
-    if uri.path().starts_with("/static") {
-        let path = &uri.path()["/static/".len()..];
-        let f = STATIC_DIR.read_to_string(path)?;
-        let mime = mime_guess::from_path(path)
-            .first()
-            .ok_or_else(|| anyhow!("couldn't guess mimetype for static file"))?
-            .to_string();
-        let mut resp = (StatusCode::OK, f).into_response();
+    // The link checker knows these, see `orgish::check::SERVED_PATHS`.
+    if let Some(path) = uri.path().strip_prefix("/static/") {
+        serve_static_file(&STATIC_DIR, path, req.headers()).await
+    } else if let (Some(path), Some(cache)) =
+        (uri.path().strip_prefix("/image-cache/"), image_cache_dir())
+    {
+        serve_static_file(cache, path, req.headers()).await
+    } else if uri.path() == "/feed.xml" {
+        let ast = current_ast()?;
+        let images = current_images(&ast).await?;
+        let feed = render_feed(&ast, &base_url(req.headers())?, &images)?;
+        Ok(([(CONTENT_TYPE, "application/atom+xml")], feed).into_response())
+    } else if uri.path() == "/sitemap.xml" {
+        let ast = current_ast()?;
+        let sitemap = render_sitemap(&ast, &base_url(req.headers())?)?;
+        Ok(([(CONTENT_TYPE, "application/xml")], sitemap).into_response())
+    } else if uri.path() == "/robots.txt" {
+        // Without a host to point at, robots.txt just goes without the sitemap line.
+        let base_url = base_url(req.headers()).ok();
+        Ok(render_robots(base_url.as_deref())?.into_response())
+    } else {
+        let viewer = viewer(uri, req.headers())?;
+        if let Some(cookie) = viewer.set_cookie {
+            // The cookie carries the token from here on. Left in the address, it would end up in
+            // the history, server logs and Referer headers.
+            return Ok((
+                StatusCode::SEE_OTHER,
+                [
+                    (LOCATION, HeaderValue::from_str(&without_token(uri))?),
+                    (SET_COOKIE, HeaderValue::from_str(&cookie)?),
+                ],
+            )
+                .into_response());
+        }
 
-        resp.headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_str(&mime).unwrap()); // TODO fix result mess so this can be ?Try
+        let ast = current_ast()?;
+        let images = current_images(&ast).await?;
 
-        Ok(resp)
-    } else {
-        let liquid_parser = ParserBuilder::with_stdlib().build()?;
-        let mut owned_ast: MaybeUninit<PassedSyntaxTree> = MaybeUninit::uninit();
-        let ast = if ARGS.cache_org {
-            AST.deref().as_ref().unwrap()
+        // Restricted pages are stripped out entirely, so they 404 like any other missing page.
+        let ast = strip_restricted(&ast, &viewer.groups);
+
+        // The reserved pages are only ever shown in place of another one.
+        let page = if is_reserved(&uri.path()[1..]) {
+            None
         } else {
-            let org_file = CONTENT_DIR.read_to_string("index.org")?;
-            owned_ast.write(parse_n_pass(&org_file)?);
-            // Safety: we *just* wrote to this, so it's safe to assume:
-            // Need to manually drop.
-            unsafe { owned_ast.assume_init_ref() }
+            render_page(&ast, uri, output_format, &images)?
         };
+        Ok(match (find_redirect(&ast, uri), page) {
+            (Some(target), _) => (
+                StatusCode::PERMANENT_REDIRECT,
+                [(LOCATION, HeaderValue::from_str(target)?)],
+            )
+                .into_response(),
+            (None, Some(json)) if output_format == OutputFormat::Json => {
+                ([(CONTENT_TYPE, "application/json")], json).into_response()
+            }
+            (None, Some(html)) => Html(dev_html(html)).into_response(),
+            (None, None) => match render_page(&ast, &NOT_FOUND_URI, OutputFormat::Html, &images)? {
+                Some(html) => (StatusCode::NOT_FOUND, Html(dev_html(html))).into_response(),
+                None => (StatusCode::NOT_FOUND, "nothing here!".to_string()).into_response(),
+            },
+        })
+    }
+}
+
+/// --base-url, or a guess from the Host header
+fn base_url(headers: &HeaderMap) -> Result<String> {
+    match (&ARGS.base_url, headers.get(HOST)) {
+        (Some(base_url), _) => Ok(base_url.trim_end_matches('/').to_string()),
+        (None, Some(host)) => Ok(format!("http://{}", host.to_str()?)),
+        (None, None) => bail!("no --base-url and no Host header to make absolute links with"),
+    }
+}
+
+/// The cached tree with --cache-org, a fresh parse otherwise.
+fn current_ast() -> Result<Arc<PassedSyntaxTree>> {
+    Ok(if ARGS.cache_org {
+        AST.read().unwrap().clone()
+    } else {
+        Arc::new(load_ast()?)
+    })
+}
+
+/// The images of `ast`, cached with --cache-org. Otherwise they are looked up on a blocking
+/// thread, since making smaller copies of a big image would hold up every other request.
+async fn current_images(ast: &Arc<PassedSyntaxTree>) -> Result<Arc<ImageInfos>> {
+    if ARGS.cache_org {
+        return Ok(IMAGES.read().unwrap().clone());
+    }
+    let ast = ast.clone();
+    Ok(Arc::new(
+        tokio::task::spawn_blocking(move || image_infos(&ast)).await?,
+    ))
+}
+
+fn inject_reload_script(mut html: String) -> String {
+    match html.rfind("</body>") {
+        Some(idx) => html.insert_str(idx, RELOAD_SCRIPT),
+        None => html.push_str(RELOAD_SCRIPT),
+    }
+    html
+}
+
+/// With --dev, pages reload themselves when the content changes.
+fn dev_html(html: String) -> String {
+    if ARGS.dev {
+        inject_reload_script(html)
+    } else {
+        html
+    }
+}
+
+/// `:404:` and `:500:` can't be visited, they stand in for other pages.
+pub fn is_reserved(pg: &str) -> bool {
+    pg == &NOT_FOUND_URI.path()[1..] || pg == &ERROR_URI.path()[1..]
+}
+
+/// An `.org` file of the content folder, and everything wrong with it: parse errors, broken links
+/// and footnote mistakes.
+pub struct ContentFile {
+    pub name: String,
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
 
-        for node in ast {
-            match &node.inner {
-                // we trim off the first byte since it's probably `/` and that doesn't match the hashmap keys
-                // sure do hope it's not some unicode scalar that will do really weird things and make us panic
-                AstNode::Heading {
-                    routing: Some(Route::Page(pg)),
-                    title,
-                    children,
-                    ..
-                } if pg == &uri.path()[1..] => {
-                    let liquid_page = CONTENT_DIR.read_to_string("page.liquid")?;
-                    let template = liquid_parser.parse(&liquid_page)?;
-                    return match output_format {
-                        OutputFormat::Html => {
-                            let html_buffers = ast_to_html_string(children, OutputTo::Main)?;
-                            let globals = object!({
-                                "req_path": format!("{}", uri),
-                                "html": html_buffers.main,
-                                "nav_htmls": html_buffers.nav,
-                                "nav_htmls_len": html_buffers.nav.len(),
-                                "title": stringify_bet(&title)?,
-                                "html_title": bet_to_html_string(&title)?,
-                                "format": "html"
-                            });
-
-                            Ok(Html(template.render(&globals)?).into_response())
-                        }
-
-                        OutputFormat::Ast => {
-                            let globals = object!({
-                                "req_path": format!("{}", uri),
-                                "html": format!("<pre>{children:#?}</pre>"),
-                                "nav_htmls_len": 0,
-                                "title": format!("AST dump of {}", stringify_bet(&title)?),
-                                "html_title": format!(r#"<code>AST</code> dump of "{}""#, bet_to_html_string(&title)?),
-                                "format": "ast"
-                            });
-
-                            Ok(Html(template.render(&globals)?).into_response())
-                        }
-                    };
+/// Every `.org` file other than index.org, sorted so the tree doesn't depend on the file system's
+/// whims.
+pub fn page_file_names() -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in CONTENT_DIR.entries()? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                warn!("skipping {name:?} in the content folder, its name isn't UTF-8");
+                continue;
+            }
+        };
+        if name.ends_with(".org") && name != "index.org" && entry.file_type()?.is_file() {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Parses index.org and every other `.org` file in the content folder, which are pages of their own.
+fn parse_content() -> Result<(PassedSyntaxTree, Vec<ContentFile>)> {
+    let main = CONTENT_DIR.read_to_string("index.org")?;
+    let names = page_file_names()?;
+    let sources = names
+        .iter()
+        .map(|name| CONTENT_DIR.read_to_string(name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let pages = names
+        .iter()
+        .zip(&sources)
+        .map(|(name, source)| PageFile { name, source })
+        .collect::<Vec<_>>();
+    let ParsedPages {
+        ast,
+        main_len,
+        diagnostics,
+    } = parse_n_pass_pages(&main, &pages)?;
+    let checks = check_files(&ast, main_len)?;
+
+    let files = iter::once("index.org".to_string())
+        .chain(names)
+        .zip(iter::once(main).chain(sources))
+        .zip(diagnostics.into_iter().zip(checks))
+        .map(|((name, source), (mut diagnostics, checks))| {
+            diagnostics.extend(checks);
+            ContentFile {
+                name,
+                source,
+                diagnostics,
+            }
+        })
+        .collect();
+    Ok((ast, files))
+}
+
+/// Parses the content folder. Parts that fail to parse are logged and rendered as errors, so one
+/// typo doesn't take the whole site down. Broken links and footnote mistakes are logged too.
+pub fn load_ast() -> Result<PassedSyntaxTree> {
+    let (ast, files) = parse_content()?;
+    for file in files {
+        for diagnostic in file.diagnostics {
+            warn!("{}:{diagnostic}", file.name);
+        }
+    }
+    Ok(ast)
+}
+
+/// The `:500:` page as anonymous viewers see it, or `None` if there isn't one.
+/// With --dev, `err` and everything wrong with the content folder go below it, with the source
+/// around each problem, so there is no need to go dig through the terminal.
+pub fn render_error_page(err: &anyhow::Error) -> Result<Option<String>> {
+    let mut details = String::new();
+    let mut fresh_ast = None;
+    // Parsing everything again for the diagnostics is only worth it in dev.
+    if ARGS.dev {
+        write!(
+            details,
+            r#"<pre class="error">{}</pre>"#,
+            escape_html(&format!("{err:#}"))
+        )?;
+        match parse_content() {
+            Ok((ast, files)) => {
+                for file in &files {
+                    for diagnostic in &file.diagnostics {
+                        details.push_str(&diagnostic.to_html(&file.name, &file.source));
+                    }
                 }
-                _ => {}
+                fresh_ast = Some(ast);
             }
+            Err(parse_err) => write!(
+                details,
+                r#"<p>The content couldn't be loaded:</p><pre class="error">{}</pre>"#,
+                escape_html(&format!("{parse_err:#}"))
+            )?,
         }
+    }
 
-        // Safety: owned_ast must be initalized.
-        unsafe { if !ARGS.cache_org { owned_ast.assume_init_drop() } };
+    match error_page(fresh_ast, &details) {
+        Ok(Some(html)) => Ok(Some(dev_html(html))),
+        Ok(None) if !ARGS.dev => Ok(None),
+        Err(page_err) if !ARGS.dev => Err(page_err),
+        // Still worth showing in dev when there is no error page, or it broke too (page.liquid
+        // is a likely culprit), just without the nice looks.
+        page => {
+            if let Err(page_err) = page {
+                write!(
+                    details,
+                    r#"<p>The <code>:500:</code> page broke too:</p><pre class="error">{}</pre>"#,
+                    escape_html(&format!("{page_err:#}"))
+                )?;
+            }
+            Ok(Some(inject_reload_script(format!(
+                r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>500</title></head><body>{details}</body></html>"#
+            ))))
+        }
+    }
+}
 
-        Ok((StatusCode::NOT_FOUND, "nothing here!".to_string()).into_response())
+/// Where the page `uri` points at moved to, if it has a `#+REDIRECT:`.
+pub fn find_redirect<'a>(ast: &'a PassedSyntaxTree, uri: &Uri) -> Option<&'a str> {
+    ast.iter().find_map(|node| match &node.inner {
+        AstNode::Heading {
+            routing: Some(Route::Page(pg)),
+            children,
+            ..
+        } if pg == &uri.path()[1..] => page_redirect(children),
+        _ => None,
+    })
+}
+
+/// Renders the page `uri` points at through page.liquid, or returns `None` if there is no such page.
+/// Both the server and `site build` go through here so their output stays the same.
+pub fn render_page(
+    ast: &PassedSyntaxTree,
+    uri: &Uri,
+    output_format: OutputFormat,
+    images: &ImageInfos,
+) -> Result<Option<String>> {
+    render_page_with(ast, uri, output_format, images, "")
+}
+
+/// The `:500:` page with `details` in it. It comes from the cached tree with --cache-org, so a
+/// broken parse can't take it down too, and from `fresh_ast` (or a fresh parse) otherwise.
+fn error_page(fresh_ast: Option<PassedSyntaxTree>, details: &str) -> Result<Option<String>> {
+    let ast = match fresh_ast {
+        Some(ast) if !ARGS.cache_org => Arc::new(ast),
+        _ => current_ast()?,
+    };
+    let ast = strip_restricted(&ast, &HashSet::new());
+    // Without the cache, looking images up could mean making smaller copies of them. The error
+    // page can do without.
+    let images = if ARGS.cache_org {
+        IMAGES.read().unwrap().clone()
+    } else {
+        Arc::default()
+    };
+    render_page_with(&ast, &ERROR_URI, OutputFormat::Html, &images, details)
+}
+
+/// [`render_page`], with `extra_html` added to the end of the page's own HTML.
+fn render_page_with(
+    ast: &PassedSyntaxTree,
+    uri: &Uri,
+    output_format: OutputFormat,
+    images: &ImageInfos,
+    extra_html: &str,
+) -> Result<Option<String>> {
+    let liquid_parser = ParserBuilder::with_stdlib().build()?;
+
+    for node in ast {
+        match &node.inner {
+            // we trim off the first byte since it's probably `/` and that doesn't match the hashmap keys
+            // sure do hope it's not some unicode scalar that will do really weird things and make us panic
+            AstNode::Heading {
+                routing: Some(Route::Page(pg)),
+                title,
+                children,
+                ..
+            } if pg == &uri.path()[1..] => {
+                if output_format == OutputFormat::Json {
+                    return Ok(Some(to_json(slice::from_ref(node))?));
+                }
+
+                let liquid_page = CONTENT_DIR.read_to_string("page.liquid")?;
+                let template = liquid_parser.parse(&liquid_page)?;
+                let globals = match output_format {
+                    OutputFormat::Html => {
+                        let html_buffers = ast_to_html_string(children, OutputTo::Main, images)?;
+                        object!({
+                            "req_path": format!("{}", uri),
+                            "html": html_buffers.main + extra_html,
+                            "nav_htmls": html_buffers.nav,
+                            "nav_htmls_len": html_buffers.nav.len(),
+                            "toc_html": page_toc_html(children).unwrap_or_default(),
+                            "title": escape_html(&stringify_bet(title)?),
+                            "html_title": bet_to_html_string(title, images)?,
+                            "format": "html"
+                        })
+                    }
+
+                    OutputFormat::Json => unreachable!(),
+
+                    OutputFormat::Ast => object!({
+                        "req_path": format!("{}", uri),
+                        "html": format!("<pre>{}</pre>", escape_html(&format!("{children:#?}"))),
+                        "nav_htmls": Vec::<String>::new(),
+                        "nav_htmls_len": 0,
+                        "toc_html": "",
+                        "title": escape_html(&format!("AST dump of {}", stringify_bet(title)?)),
+                        "html_title": format!(r#"<code>AST</code> dump of "{}""#, bet_to_html_string(title, images)?),
+                        "format": "ast"
+                    }),
+                };
+
+                return Ok(Some(template.render(&globals)?));
+            }
+            _ => {}
+        }
     }
+
+    Ok(None)
 }
@@ -0,0 +1,127 @@
+//! `/sitemap.xml` and `/robots.txt`, so search engines can find the pages.
+//! A `robots.txt` in the content folder is served as-is instead of the generated one.
+
+use anyhow::Result;
+use orgish::{
+    access::strip_restricted,
+    parse::{
+        is_draft, page_redirect, page_source_file, AstNode, Directive, PassedSyntaxTree, Route,
+    },
+    treewalk::escape_html,
+};
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    io::ErrorKind,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    feed::atom_date,
+    serve::{is_reserved, CONTENT_DIR, INDEX_URI},
+};
+
+/// (page, date, source file) of every page anonymous viewers can see, except drafts,
+/// `#+REDIRECT:`s and the `:404:` and `:500:` pages. Pages in index.org have no source file.
+fn public_pages(ast: &PassedSyntaxTree) -> Vec<(String, Option<String>, Option<String>)> {
+    let mut pages = vec![];
+    for node in strip_restricted(ast, &HashSet::new()) {
+        let (pg, children) = match node.inner {
+            AstNode::Heading {
+                routing: Some(Route::Page(pg)),
+                children,
+                ..
+            } => (pg, children),
+            _ => continue,
+        };
+
+        if page_redirect(&children).is_some() || is_draft(&children) || is_reserved(&pg) {
+            continue;
+        }
+        let date = children.iter().find_map(|child| match &child.inner {
+            AstNode::Directive(Directive::Date(date)) => atom_date(date),
+            _ => None,
+        });
+        let file = page_source_file(&children).map(str::to_string);
+        pages.push((pg, date, file));
+    }
+    pages
+}
+
+/// YYYY-MM-DD in UTC
+fn w3c_date(time: SystemTime) -> String {
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+        / 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+/// When `file` in the content folder was last changed, if there is such a file.
+fn file_date(file: &str) -> Option<String> {
+    let meta = CONTENT_DIR.metadata(file).ok()?;
+    Some(w3c_date(meta.modified().ok()?.into_std()))
+}
+
+/// Pages without a `#+DATE:` were last changed whenever the file they're in was: their own file,
+/// or index.org for the pages in there.
+pub fn render_sitemap(ast: &PassedSyntaxTree, base_url: &str) -> Result<String> {
+    let mtime = w3c_date(CONTENT_DIR.metadata("index.org")?.modified()?.into_std());
+
+    let mut buf = String::new();
+    writeln!(buf, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(
+        buf,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+    for (pg, date, file) in public_pages(ast) {
+        let date = match date {
+            Some(date) => date,
+            None => file
+                .as_deref()
+                .and_then(file_date)
+                .unwrap_or_else(|| mtime.clone()),
+        };
+        // The index page lives at `/` as far as anyone outside is concerned.
+        let path = if pg == INDEX_URI.path()[1..] { "" } else { &pg };
+        writeln!(buf, "<url>")?;
+        writeln!(
+            buf,
+            "<loc>{}</loc>",
+            escape_html(&format!("{base_url}/{path}"))
+        )?;
+        writeln!(buf, "<lastmod>{date}</lastmod>")?;
+        writeln!(buf, "</url>")?;
+    }
+    writeln!(buf, "</urlset>")?;
+
+    Ok(buf)
+}
+
+/// Drafts are kept out of search engines by leaving them out of the sitemap, listing them here
+/// would only advertise them. Without a `base_url` there is no `Sitemap:` line, since it has to
+/// be absolute.
+pub fn render_robots(base_url: Option<&str>) -> Result<String> {
+    match CONTENT_DIR.read_to_string("robots.txt") {
+        Ok(robots) => return Ok(robots),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut buf = "User-agent: *\nAllow: /\n".to_string();
+    if let Some(base_url) = base_url {
+        writeln!(buf, "\nSitemap: {base_url}/sitemap.xml")?;
+    }
+    Ok(buf)
+}
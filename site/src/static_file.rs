@@ -0,0 +1,154 @@
+use std::{
+    io::{self, SeekFrom},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use axum::{
+    body::StreamBody,
+    http::{HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use cap_std::fs::Dir;
+use hyper::{
+    header::{
+        ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+        IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+    },
+    StatusCode,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Serve a file from `dir` as bytes, with conditional and range request support.
+///
+/// `dir` is a capability, so `path` can't escape it no matter how many `..` it has.
+pub async fn serve_static_file(dir: &Dir, path: &str, req_headers: &HeaderMap) -> Result<Response> {
+    let file = match dir.open(path) {
+        Ok(f) => f,
+        // Escaping the Dir is a PermissionDenied, that's nobody's business though.
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            return Ok((StatusCode::NOT_FOUND, "nothing here!".to_string()).into_response());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Ok((StatusCode::NOT_FOUND, "nothing here!".to_string()).into_response());
+    }
+
+    let len = metadata.len();
+    let modified = metadata.modified()?.into_std();
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let etag = format!(r#""{:x}-{:x}""#, len, since_epoch.as_nanos());
+    // HTTP dates only have second precision, so compare with that.
+    let modified_secs = UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, HeaderValue::from_str(&etag)?);
+    headers.insert(
+        LAST_MODIFIED,
+        HeaderValue::from_str(&httpdate::fmt_http_date(modified))?,
+    );
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(mime_guess::from_path(path).first_or_octet_stream().as_ref())?,
+    );
+
+    let header_str = |name| {
+        req_headers
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+    };
+
+    // If-None-Match wins over If-Modified-Since, see RFC 7232 section 6.
+    let not_modified = match (header_str(IF_NONE_MATCH), header_str(IF_MODIFIED_SINCE)) {
+        (Some(inm), _) => inm
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || t == etag),
+        (None, Some(ims)) => {
+            httpdate::parse_http_date(ims).map_or(false, |ims| modified_secs <= ims)
+        }
+        (None, None) => false,
+    };
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    // A stale If-Range means the client wants the whole new file instead.
+    let range_applies = match header_str(IF_RANGE) {
+        Some(if_range) if if_range.starts_with('"') => if_range == etag,
+        Some(if_range) => httpdate::parse_http_date(if_range).map_or(false, |d| d == modified_secs),
+        None => true,
+    };
+    let range = match header_str(RANGE) {
+        Some(range) if range_applies => parse_range(range, len),
+        _ => None,
+    };
+
+    let (status, start, count) = match range {
+        None => (StatusCode::OK, 0, len),
+        Some(Ok((start, end))) => {
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len))?,
+            );
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        Some(Err(())) => {
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len))?,
+            );
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(count));
+
+    let mut file = tokio::fs::File::from_std(file.into_std());
+    file.seek(SeekFrom::Start(start)).await?;
+    let body = StreamBody::new(ReaderStream::new(file.take(count)));
+
+    Ok((status, headers, body).into_response())
+}
+
+/// Parse a `Range: bytes=...` header into an inclusive byte range.
+///
+/// Returns `None` if the header should be ignored, and `Some(Err(()))` if it can't be satisfied.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // Multiple ranges would need multipart/byteranges, so everyone gets the whole file instead.
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // bytes=-500 is the last 500 bytes
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(len.saturating_sub(1)),
+        ),
+    };
+
+    if start > end || start >= len {
+        Some(Err(()))
+    } else {
+        Some(Ok((start, end)))
+    }
+}